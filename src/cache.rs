@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use crate::log;
+
+//objects/<first two chars>/<hash>, plus "refs": one "<hash> <path>" line per file linked from the store
+pub struct Store {
+    root: PathBuf,
+}
+
+//what `cache gc` freed
+pub struct GcReport {
+    pub objects: usize,
+    pub bytes: u64,
+}
+
+static STORE: OnceLock<Store> = OnceLock::new();
+
+//a new, empty file at path. What was there is unlinked rather than truncated: it may be a hardlink of a
+//stored object, shared with every other instance
+pub fn create_file(path: &Path) -> std::io::Result<std::fs::File> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::fs::File::create(path)
+}
+
+//set once at startup, the cache stays disabled without it
+pub fn init(store: Store) {
+    let _ = STORE.set(store);
+}
+
+pub fn shared() -> Option<&'static Store> {
+    STORE.get()
+}
+
+//a hardlink when the filesystem allows it (FAT32 doesn't), else a copy
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(from, to).map(|_| ())
+}
+
+//recorded relative to the portable root when possible, the stick's drive letter changes between machines
+fn portable_path(path: &Path) -> PathBuf {
+    std::env::current_dir()
+        .ok()
+        .and_then(|root| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+impl Store {
+    pub fn new<P: AsRef<Path>>(root: P) -> Store {
+        Store { root: root.as_ref().to_path_buf() }
+    }
+
+    fn object_path(&self, hash: &str) -> Option<PathBuf> {
+        let valid = hash.len() > 2 && hash.chars().all(|c| c.is_ascii_hexdigit());
+        valid.then(|| self.root.join("objects").join(&hash[..2]).join(hash))
+    }
+
+    //a temporary directory on the store's filesystem, files inserted from it can be hardlinked
+    pub fn staging_dir(&self) -> std::io::Result<tempfile::TempDir> {
+        std::fs::create_dir_all(&self.root)?;
+        tempfile::Builder::new().prefix("staging").tempdir_in(&self.root)
+    }
+
+    //path of a stored object, if the store has it
+    pub fn lookup(&self, hash: &str) -> Option<PathBuf> {
+        self.object_path(hash).filter(|o| o.exists())
+    }
+
+    fn record(&self, hash: &str, target: &Path) {
+        let line = format!("{} {}\n", hash, portable_path(target).display());
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.root.join("refs"))
+            .and_then(|mut refs| refs.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            log!("WARN", target.display(), "cache reference not recorded: {}", e);
+        }
+    }
+
+    //put the stored object at target, true when the store had it. The caller still verifies the result
+    pub fn restore(&self, hash: &str, target: &Path) -> bool {
+        let Some(object) = self.lookup(hash) else {
+            return false;
+        };
+        if let Some(parent) = target.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = std::fs::remove_file(target);
+        match link_or_copy(&object, target) {
+            Ok(()) => {
+                self.record(hash, target);
+                true
+            }
+            Err(e) => {
+                log!("WARN", target.display(), "cache restore failed: {}", e);
+                false
+            }
+        }
+    }
+
+    //add a verified file to the store, the file itself stays where it is
+    pub fn insert(&self, path: &Path, hash: &str) {
+        let Some(object) = self.object_path(hash) else {
+            return;
+        };
+        if !object.exists() {
+            let result = std::fs::create_dir_all(object.parent().expect("object has a parent"))
+                .and_then(|_| link_or_copy(path, &object));
+            if let Err(e) = result {
+                log!("WARN", path.display(), "not added to the cache: {}", e);
+                return;
+            }
+        }
+        self.record(hash, path);
+    }
+
+    //drop an object found corrupted, e.g. edited in place through one of its hardlinks
+    pub fn forget(&self, hash: &str) {
+        if let Some(object) = self.object_path(hash) {
+            let _ = std::fs::remove_file(object);
+        }
+    }
+
+    //remove the objects no file refers to anymore. A reference holds while its file exists with the object's size
+    pub fn gc(&self) -> std::io::Result<GcReport> {
+        let refs = match std::fs::read_to_string(self.root.join("refs")) {
+            Ok(refs) => refs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut live = HashSet::new();
+        for line in refs.lines() {
+            let Some((hash, path)) = line.split_once(' ') else {
+                continue;
+            };
+            let Some(object) = self.object_path(hash) else {
+                continue;
+            };
+            let (Ok(object_meta), Ok(file_meta)) = (std::fs::metadata(&object), std::fs::metadata(path)) else {
+                continue;
+            };
+            if object_meta.len() == file_meta.len() {
+                live.insert(line);
+            }
+        }
+        let live_hashes = live.iter().filter_map(|l| l.split(' ').next()).collect::<HashSet<_>>();
+
+        let mut report = GcReport { objects: 0, bytes: 0 };
+        let Ok(prefixes) = std::fs::read_dir(self.root.join("objects")) else {
+            return Ok(report);
+        };
+        for prefix in prefixes.flatten() {
+            for object in std::fs::read_dir(prefix.path())?.flatten() {
+                let hash = object.file_name().to_string_lossy().into_owned();
+                if live_hashes.contains(hash.as_str()) {
+                    continue;
+                }
+                report.bytes += object.metadata().map(|m| m.len()).unwrap_or(0);
+                std::fs::remove_file(object.path())?;
+                report.objects += 1;
+            }
+            let _ = std::fs::remove_dir(prefix.path());
+        }
+        let refs = live.iter().map(|l| format!("{}\n", l)).collect::<String>();
+        std::fs::write(self.root.join("refs"), refs)?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_objects_and_collects_unreferenced_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().join("cache"));
+        let (first, second) = (dir.path().join("a/mods/x.jar"), dir.path().join("b/mods/x.jar"));
+        std::fs::create_dir_all(first.parent().unwrap()).unwrap();
+        std::fs::write(&first, b"content").unwrap();
+
+        store.insert(&first, "abcdef");
+        assert!(store.restore("abcdef", &second));
+        assert_eq!(std::fs::read(&second).unwrap(), b"content");
+        assert!(!store.restore("012345", &second));
+
+        std::fs::remove_file(&first).unwrap();
+        assert_eq!(store.gc().unwrap().objects, 0);
+        std::fs::remove_file(&second).unwrap();
+        let report = store.gc().unwrap();
+        assert_eq!((report.objects, report.bytes), (1, 7));
+        assert!(!store.restore("abcdef", &second));
+    }
+
+    #[test]
+    fn created_files_leave_the_stored_object_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().join("cache"));
+        let path = dir.path().join("mods/x.jar");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"content").unwrap();
+        store.insert(&path, "abcdef");
+
+        std::io::Write::write_all(&mut create_file(&path).unwrap(), b"edited").unwrap();
+
+        assert_eq!(std::fs::read(store.lookup("abcdef").unwrap()).unwrap(), b"content");
+        assert_eq!(std::fs::read(&path).unwrap(), b"edited");
+    }
+}
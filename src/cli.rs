@@ -0,0 +1,280 @@
+use std::path::Path;
+use crate::download::HashAlgorithm;
+use crate::instance::{Instance, InstanceConfig};
+use crate::publish::{self, GenerateOptions};
+use crate::{cache, env, java, modpack, network, peer, signature};
+
+pub enum Command {
+    Launch { instance: Option<String>, offline: bool },
+    JavaList,
+    InstanceList,
+    InstanceCreate { name: String, version: Option<String> },
+    InstanceDelete { name: String },
+    InstanceClone { from: String, to: String },
+    ModpackInstall { instance: String, source: String },
+    CacheGc,
+    PeerServe,
+    Prefetch { instance: Option<String> },
+    Bandwidth { limit: u64 },
+    ManifestKeygen { secret: String },
+    ManifestSign { manifest: String, secret: String },
+    ManifestGenerate { dir: String, output: String, secret: Option<String>, options: GenerateOptions },
+}
+
+const USAGE: &str = "usage: Minecraft [--instance <name>] [--offline]
+       Minecraft java list
+       Minecraft instance list
+       Minecraft instance create <name> [--version <version>]
+       Minecraft instance delete <name>
+       Minecraft instance clone <from> <to>
+       Minecraft modpack install <instance> <file.mrpack|file.zip|url>
+       Minecraft cache gc
+       Minecraft peer serve
+       Minecraft prefetch [--instance <name>]
+       Minecraft bandwidth <bytes/s|500K|2M|unlimited>
+       Minecraft manifest keygen <secret.key>
+       Minecraft manifest sign <files.json> <secret.key>
+       Minecraft manifest generate <dir> --base-url <url> [--include <glob>]... [--exclude <glob>]...
+                                   [--version <n>] [--changelog <text>] [--algorithm <sha1|sha256|sha512|md5>]
+                                   [--output <files.json>] [--sign <secret.key>]";
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let args = args.collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["java", "list"] => Ok(Command::JavaList),
+        ["instance", "list"] => Ok(Command::InstanceList),
+        ["instance", "create", name] => Ok(Command::InstanceCreate { name: name.to_string(), version: None }),
+        ["instance", "create", name, "--version", version] => Ok(Command::InstanceCreate {
+            name: name.to_string(),
+            version: Some(version.to_string()),
+        }),
+        ["instance", "delete", name] => Ok(Command::InstanceDelete { name: name.to_string() }),
+        ["instance", "clone", from, to] => Ok(Command::InstanceClone { from: from.to_string(), to: to.to_string() }),
+        ["modpack", "install", instance, source] => Ok(Command::ModpackInstall {
+            instance: instance.to_string(),
+            source: source.to_string(),
+        }),
+        ["cache", "gc"] => Ok(Command::CacheGc),
+        ["peer", "serve"] => Ok(Command::PeerServe),
+        ["prefetch"] => Ok(Command::Prefetch { instance: None }),
+        ["prefetch", "--instance", name] => Ok(Command::Prefetch { instance: Some(name.to_string()) }),
+        ["bandwidth", rate] => network::parse_rate(rate)
+            .map(|limit| Command::Bandwidth { limit })
+            .ok_or_else(|| String::from(USAGE)),
+        ["manifest", "keygen", secret] => Ok(Command::ManifestKeygen { secret: secret.to_string() }),
+        ["manifest", "sign", manifest, secret] => Ok(Command::ManifestSign {
+            manifest: manifest.to_string(),
+            secret: secret.to_string(),
+        }),
+        ["manifest", "generate", dir, options @ ..] => parse_generate(dir, options).ok_or_else(|| String::from(USAGE)),
+        launch => parse_launch(launch).ok_or_else(|| String::from(USAGE)),
+    }
+}
+
+//[--instance <name>] [--offline], in any order
+fn parse_launch(args: &[&str]) -> Option<Command> {
+    let (mut instance, mut offline) = (None, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--offline" if !offline => offline = true,
+            "--instance" if instance.is_none() => instance = Some(args.next()?.to_string()),
+            _ => return None,
+        }
+    }
+    Some(Command::Launch { instance, offline })
+}
+
+fn parse_generate(dir: &str, args: &[&str]) -> Option<Command> {
+    let (mut output, mut secret, mut options) = (String::from("files.json"), None, GenerateOptions::default());
+    for pair in args.chunks(2) {
+        let [option, value] = pair else {
+            return None;
+        };
+        let value = value.to_string();
+        match *option {
+            "--base-url" => options.base_url = value,
+            "--include" => options.include.push(value),
+            "--exclude" => options.exclude.push(value),
+            "--version" => options.version = Some(value.parse().ok()?),
+            "--changelog" => options.changelog = Some(value),
+            "--algorithm" => options.algorithm = HashAlgorithm::from_name(&value)?,
+            "--output" => output = value,
+            "--sign" => secret = Some(value),
+            _ => return None,
+        }
+    }
+    (!options.base_url.is_empty()).then(|| Command::ManifestGenerate { dir: dir.to_string(), output, secret, options })
+}
+
+//runs a command other than Launch, the error is for the caller to print
+pub async fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Launch { .. } => Ok(()),
+        Command::JavaList => {
+            java_list();
+            Ok(())
+        }
+        Command::InstanceList => instance_list().map_err(|e| e.to_string()),
+        Command::InstanceCreate { name, version } => Instance::create(&name, InstanceConfig { version, ..Default::default() })
+            .map(|instance| println!("Created {} in {}", instance.name, instance.game_dir.display()))
+            .map_err(|e| e.to_string()),
+        Command::InstanceDelete { name } => Instance::delete(&name)
+            .map(|_| println!("Deleted {}", name))
+            .map_err(|e| e.to_string()),
+        Command::InstanceClone { from, to } => Instance::select(Some(&from))
+            .and_then(|instance| instance.clone_to(&to))
+            .map(|instance| println!("Cloned {} to {}", from, instance.name))
+            .map_err(|e| e.to_string()),
+        Command::ModpackInstall { instance, source } => {
+            let (instance, info) = modpack::install(&source, &instance).await.map_err(|e| e.to_string())?;
+            println!("Installed {} into {} ({})", source, instance.name, instance.version());
+            if !info.manual_downloads.is_empty() {
+                println!("{} files must be downloaded by hand into {}:", info.manual_downloads.len(), instance.game_dir.display());
+                for file in &info.manual_downloads {
+                    println!("  {:<40} -> {}  {}", file.name, file.target, file.page);
+                }
+            }
+            Ok(())
+        }
+        Command::CacheGc => {
+            let report = cache::Store::new(env::CACHE_DIR).gc().map_err(|e| e.to_string())?;
+            println!("Removed {} objects, {:.1} MB reclaimed", report.objects, report.bytes as f64 / 1_048_576.0);
+            Ok(())
+        }
+        Command::PeerServe => peer::serve(env::CONFIG.peers.port).await.map_err(|e| e.to_string()),
+        Command::Prefetch { instance } => {
+            let instance = Instance::select(instance.as_deref()).map_err(|e| e.to_string())?;
+            let count = network::background(instance.prefetch()).await.map_err(|e| e.to_string())?;
+            println!("Prefetched {} files for {}", count, instance.name);
+            Ok(())
+        }
+        Command::Bandwidth { limit } => {
+            let path = Path::new(env::BANDWIDTH_PATH);
+            std::fs::create_dir_all(path.parent().expect("bandwidth file has a parent"))
+                .and_then(|_| std::fs::write(path, limit.to_string()))
+                .map_err(|e| e.to_string())?;
+            println!("Bandwidth limit of the running launchers set to {} bytes/s", limit);
+            Ok(())
+        }
+        Command::ManifestKeygen { secret } => {
+            let public = signature::generate(Path::new(&secret)).map_err(|e| e.to_string())?;
+            println!("Secret key written to {}, keep it private. Public key for manifest_keys:\n{}", secret, public);
+            Ok(())
+        }
+        Command::ManifestGenerate { dir, output, secret, options } => {
+            let files = publish::generate(Path::new(&dir), &options).map_err(|e| e.to_string())?;
+            serde_json::to_vec_pretty(&files)
+                .map_err(std::io::Error::from)
+                .and_then(|json| std::fs::write(&output, json))
+                .map_err(|e| e.to_string())?;
+            println!("{} files listed in {}", files.files.len(), output);
+            if let Some(secret) = secret {
+                let signature = signature::sign(Path::new(&output), Path::new(&secret)).map_err(|e| e.to_string())?;
+                println!("Signature written to {}", signature);
+            }
+            Ok(())
+        }
+        Command::ManifestSign { manifest, secret } => {
+            let signature = signature::sign(Path::new(&manifest), Path::new(&secret)).map_err(|e| e.to_string())?;
+            println!("Signature written to {}, publish it next to the manifest", signature);
+            Ok(())
+        }
+    }
+}
+
+fn java_list() {
+    let mut cache = java::JavaCache::load(env::JAVA_CACHE_PATH);
+    let installations = cache.discover(Path::new(env::RUNTIME_DIR));
+    cache.save(env::JAVA_CACHE_PATH);
+    if installations.is_empty() {
+        println!("No java found");
+    }
+    for java in installations {
+        println!(
+            "{:>3}  {:<12} {:<7} {:<24} {}{}",
+            java.major,
+            java.version,
+            java.arch,
+            java.vendor,
+            java.path.display(),
+            if java.portable { "  (portable)" } else { "" },
+        );
+    }
+}
+
+fn instance_list() -> Result<(), crate::instance::InstanceError> {
+    let selected = Instance::select(None).map(|instance| instance.name).unwrap_or_default();
+    let mut instances = Instance::list()?;
+    instances.insert(0, Instance::implicit());
+    for instance in instances {
+        println!(
+            "{} {:<20} {:<16} {}",
+            if instance.name == selected { "*" } else { " " },
+            instance.name,
+            instance.version(),
+            instance.game_dir.display(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Command, String> {
+        parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_launches() {
+        let cases = [
+            ("", None, false),
+            ("--offline", None, true),
+            ("--instance survival", Some("survival"), false),
+            ("--instance survival --offline", Some("survival"), true),
+            ("--offline --instance survival", Some("survival"), true),
+        ];
+        for (args, expected_instance, expected_offline) in cases {
+            let Ok(Command::Launch { instance, offline }) = parse_args(args) else {
+                panic!("{} isn't a launch", args);
+            };
+            assert_eq!((instance.as_deref(), offline), (expected_instance, expected_offline), "{}", args);
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert!(matches!(parse_args("java list"), Ok(Command::JavaList)));
+        assert!(matches!(parse_args("instance create survival --version 1.20.1"),
+            Ok(Command::InstanceCreate { name, version: Some(version) }) if name == "survival" && version == "1.20.1"));
+        assert!(matches!(parse_args("instance clone survival creative"),
+            Ok(Command::InstanceClone { from, to }) if from == "survival" && to == "creative"));
+        assert!(matches!(parse_args("prefetch --instance survival"), Ok(Command::Prefetch { instance: Some(name) }) if name == "survival"));
+        assert!(matches!(parse_args("bandwidth 2M"), Ok(Command::Bandwidth { limit: 2_097_152 })));
+        //--offline is a value like any other here
+        let Ok(Command::ManifestGenerate { dir, output, options, .. }) = parse_args("manifest generate pack --base-url http://x --changelog --offline --output out.json") else {
+            panic!("manifest generate not parsed");
+        };
+        assert_eq!((dir.as_str(), output.as_str(), options.changelog.as_deref()), ("pack", "out.json", Some("--offline")));
+    }
+
+    #[test]
+    fn rejects_misplaced_arguments() {
+        for args in [
+            "instance list --offline",
+            "--offline java list",
+            "--offline --offline",
+            "--instance",
+            "--instance a --instance b",
+            "bandwidth fast",
+            "manifest generate pack",
+            "manifest generate pack --base-url http://x --changelog",
+            "launch",
+        ] {
+            assert!(parse_args(args).is_err(), "{}", args);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use crate::java::MemoryConfig;
+use crate::network::OfflineMode;
+use crate::log;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchMode {
+    //bootstrap MinecraftLauncher.exe and let it handle the game
+    Official,
+    //download the version ourselves and start java directly
+    Direct,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct JavaConfig {
+    //skips discovery and provisioning entirely
+    pub path: Option<String>,
+    pub runtime_manifest_url: String,
+    pub provision: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct LoaderConfig {
+    //meta servers giving the loader profiles, "<url>/versions/loader/<game>/<loader>/profile/json"
+    pub fabric_meta_url: String,
+    pub quilt_meta_url: String,
+    //maven repositories of the installer jars
+    pub forge_maven_url: String,
+    pub neoforge_maven_url: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct CurseForgeConfig {
+    pub api_url: String,
+    //sent as x-api-key, the public api refuses requests without one
+    pub api_key: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    //"http://host:port", used for every scheme. Credentials go below or in the url
+    pub proxy: Option<String>,
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    //hosts reached without the proxy, in the NO_PROXY syntax: "localhost", ".lan", "10.0.0.0/8"
+    pub no_proxy: Vec<String>,
+    //PEM files trusted on top of the system roots, for networks inspecting TLS
+    pub ca_certificates: Vec<String>,
+    pub connect_timeout_secs: u64,
+    //a download fails after this long without receiving anything
+    pub read_timeout_secs: u64,
+    pub user_agent: String,
+    //bytes/s for all downloads together, 0 for unlimited
+    pub bandwidth_limit: u64,
+    //0 for unlimited
+    pub max_connections_per_host: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct PeerConfig {
+    //of `peer serve`, over tcp for the files and udp for discovery
+    pub port: u16,
+    //peers always asked first, e.g. "http://192.168.1.5:25590"
+    pub addresses: Vec<String>,
+    //broadcast on the local network for launchers running `peer serve`
+    pub discover: bool,
+}
+
+//urls starting with origin are tried on each mirror first, with the rest of their path appended
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MirrorConfig {
+    pub origin: String,
+    pub urls: Vec<String>,
+}
+
+//launcher.json, next to the executable. Every field is optional
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub mode: LaunchMode,
+    //instance launched when none is given on the command line
+    pub instance: Option<String>,
+    //version of the implicit instance and of new ones
+    pub version: String,
+    pub version_manifest_url: String,
+    pub username: String,
+    pub java: JavaConfig,
+    pub memory: MemoryConfig,
+    pub curseforge: CurseForgeConfig,
+    pub loaders: LoaderConfig,
+    //tried in order for libraries known by their maven coordinates only, after the one they name
+    pub maven_repositories: Vec<String>,
+    //hardlink identical files of every instance to one copy in the shared cache
+    pub cache: bool,
+    //"auto" probes version_manifest_url before launching, "--offline" forces it
+    pub offline: OfflineMode,
+    pub http: HttpConfig,
+    //e.g. a LAN copy of resources.download.minecraft.net, the origin stays the last resort
+    pub mirrors: Vec<MirrorConfig>,
+    //where MinecraftLauncher.exe is downloaded from, in order
+    pub launcher_urls: Vec<String>,
+    //other launchers of the LAN sharing their cache, asked before the mirrors
+    pub peers: PeerConfig,
+    //while launching, fetch the updates of the other instances into the cache, behind the launch's own downloads
+    pub prefetch: bool,
+    //base64 ed25519 public keys, instance manifests must then be signed by one of them (or a compiled in one)
+    pub manifest_keys: Vec<String>,
+    //accept instance manifests no trusted key verifies, they then decide alone what is written and deleted
+    pub allow_unsigned_manifests: bool,
+}
+
+impl Default for JavaConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            runtime_manifest_url: String::from("https://launchermeta.mojang.com/v1/products/java-runtime/2ec0cc96c44e5a76b9c8b7c39df7210883d12871/all.json"),
+            provision: true,
+        }
+    }
+}
+
+impl Default for LoaderConfig {
+    fn default() -> Self {
+        Self {
+            fabric_meta_url: String::from("https://meta.fabricmc.net/v2"),
+            quilt_meta_url: String::from("https://meta.quiltmc.org/v3"),
+            forge_maven_url: String::from("https://maven.minecraftforge.net"),
+            neoforge_maven_url: String::from("https://maven.neoforged.net/releases"),
+        }
+    }
+}
+
+impl Default for CurseForgeConfig {
+    fn default() -> Self {
+        Self {
+            api_url: String::from("https://api.curseforge.com"),
+            api_key: None,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: vec![String::from("localhost"), String::from("127.0.0.1")],
+            ca_certificates: Vec::new(),
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            user_agent: format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            bandwidth_limit: 0,
+            max_connections_per_host: 0,
+        }
+    }
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self {
+            port: 25590,
+            addresses: Vec::new(),
+            discover: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: LaunchMode::Official,
+            instance: None,
+            version: String::from("latest-release"),
+            version_manifest_url: String::from("https://piston-meta.mojang.com/mc/game/version_manifest_v2.json"),
+            username: String::from("Player"),
+            java: JavaConfig::default(),
+            memory: MemoryConfig::default(),
+            curseforge: CurseForgeConfig::default(),
+            loaders: LoaderConfig::default(),
+            maven_repositories: vec![
+                String::from("https://libraries.minecraft.net"),
+                String::from("https://repo1.maven.org/maven2"),
+            ],
+            cache: true,
+            offline: OfflineMode::Auto,
+            http: HttpConfig::default(),
+            mirrors: Vec::new(),
+            launcher_urls: vec![String::from(crate::env::URL)],
+            peers: PeerConfig::default(),
+            prefetch: false,
+            manifest_keys: Vec::new(),
+            allow_unsigned_manifests: false,
+        }
+    }
+}
+
+impl Config {
+    //a missing file gives the defaults, an invalid one is reported and ignored
+    pub fn load<P: AsRef<Path>>(path: P) -> Config {
+        let path = path.as_ref();
+        let Ok(bytes) = std::fs::read(path) else {
+            return Config::default();
+        };
+        match serde_json::from_slice(&bytes) {
+            Ok(config) => config,
+            Err(e) => {
+                log!("ERROR", path.display(), "invalid config: {}", e);
+                Config::default()
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+use core::fmt;
+use md5::{Digest, Md5};
+use crate::config::Config;
+use crate::download::{DownloadError, Files};
+use crate::helper::WindowInfo;
+use crate::instance::{Instance, LoaderKind};
+use crate::java::{self, JavaError};
+use crate::launcher::Launcher;
+use crate::version::{self, Features, Platform, Variables, VersionError, VersionManifest};
+use crate::window::Signal;
+use crate::{env, log, network};
+
+pub enum DirectError {
+    Version(VersionError),
+    Java(JavaError),
+    Io(std::io::Error),
+    UnknownVersion(String),
+}
+
+impl fmt::Display for DirectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DirectError::Version(e) => write!(f, "{}", e),
+            DirectError::Java(e) => write!(f, "{}", e),
+            DirectError::Io(e) => write!(f, "IO error: {}", e),
+            DirectError::UnknownVersion(id) => write!(f, "Unknown version {}", id),
+        }
+    }
+}
+
+impl From<VersionError> for DirectError {
+    fn from(e: VersionError) -> Self {
+        DirectError::Version(e)
+    }
+}
+
+impl From<JavaError> for DirectError {
+    fn from(e: JavaError) -> Self {
+        DirectError::Java(e)
+    }
+}
+
+impl From<DownloadError> for DirectError {
+    fn from(e: DownloadError) -> Self {
+        DirectError::Version(e.into())
+    }
+}
+
+//uuid the vanilla server gives to offline players, md5 of "OfflinePlayer:<name>" as a version 3 uuid
+fn offline_uuid(username: &str) -> String {
+    let mut hash = Md5::digest(format!("OfflinePlayer:{}", username).as_bytes());
+    hash[6] = (hash[6] & 0x0f) | 0x30;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+    hex::encode(hash)
+}
+
+fn phase(window_info: &WindowInfo, status: &str) {
+    log!("INFO", "{}", status);
+    let _ = window_info.update(Signal::Reset);
+    let _ = window_info.update(Signal::UpdateStatus(status.to_string()));
+}
+
+//offline, the missing files of every step are gathered so they are all reported at once
+fn gather<T, E: Into<DirectError>>(result: Result<T, E>, missing: &mut Vec<String>) -> Result<Option<T>, DirectError> {
+    match result.map_err(Into::into) {
+        Ok(value) => Ok(Some(value)),
+        Err(DirectError::Version(VersionError::Download(DownloadError::Missing(paths)))) => {
+            missing.extend(paths);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn lossy(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+//download everything the instance needs and build the java command line
+pub async fn prepare(config: &Config, instance: &Instance, window_info: &WindowInfo) -> Result<Launcher, DirectError> {
+    let advance = |delta: u32| -> bool {
+        if window_info.update(Signal::AdvanceDelta(delta)).is_err() {
+            log!("WARN", "Window closed!");
+            return true;
+        }
+        false
+    };
+    let root = std::env::current_dir().map_err(DirectError::Io)?;
+    let work_dir = root.join(env::WORK_DIR);
+    let (versions_dir, libraries_dir, assets_dir) = (work_dir.join("versions"), work_dir.join("libraries"), work_dir.join("assets"));
+    let game_dir = root.join(&instance.game_dir);
+
+    phase(window_info, "Version...");
+    let manifest = VersionManifest::load(&config.version_manifest_url, &versions_dir.join("version_manifest_v2.json")).await?;
+    let entry = manifest.find(instance.version()).ok_or_else(|| DirectError::UnknownVersion(instance.version().to_string()))?;
+    let mut version = entry.fetch(&versions_dir).await?;
+    let client = version.client_file().ok_or_else(|| DirectError::UnknownVersion(version.id.clone()))?;
+    let client_path = versions_dir.join(&client.path);
+    let mut missing = Vec::new();
+    gather(Files::from(vec![client]).sync(&lossy(&versions_dir), advance).await, &mut missing)?;
+
+    //chosen for the game version, loader installers run on it too
+    phase(window_info, "Java...");
+    let java_version = version.java_version.clone().unwrap_or_default();
+    let java = match java::select_java(&config.java, &java_version.component, java_version.major_version, advance).await {
+        Ok(java) => Some(java),
+        Err(e) if network::is_offline() => {
+            log!("WARN", "{}", e);
+            missing.push(format!("java {} ({})", java_version.major_version, java_version.component));
+            None
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(loader) = &instance.config.loader {
+        phase(window_info, "Mod loader...");
+        let profile = match loader.kind {
+            LoaderKind::Fabric | LoaderKind::Quilt => version::install_loader(&config.loaders, loader, &version.id, &versions_dir).await,
+            LoaderKind::Forge | LoaderKind::NeoForge => match &java {
+                Some(java) => version::install_forge(&config.loaders, loader, &version, &work_dir, java, advance).await,
+                None => Err(VersionError::UnsupportedLoader(format!("{} without java", loader.version_id(&version.id)))),
+            },
+        };
+        let profile = match (profile, &java) {
+            //the missing java is already reported
+            (Err(_), None) => None,
+            (profile, _) => gather(profile, &mut missing)?,
+        };
+        if let Some(profile) = profile {
+            if profile.inherits_from.as_deref() != Some(version.id.as_str()) {
+                log!("WARN", profile.id, "inherits from {:?}, not {}", profile.inherits_from, version.id);
+            }
+            version = profile.merge(version);
+        }
+    }
+    let platform = Platform::current();
+    let features = Features::new();
+
+    phase(window_info, "Bibliothèques...");
+    let mut plan = version.libraries(&platform, &features);
+    plan.resolve_maven(&config.maven_repositories, &libraries_dir).await?;
+    gather(plan.files.sync(&lossy(&libraries_dir), advance).await, &mut missing)?;
+
+    let assets_index = match &version.asset_index {
+        Some(index) => {
+            phase(window_info, "Ressources...");
+            gather(version::download_assets(index, &assets_dir, &game_dir, advance).await, &mut missing)?;
+            index.id.clone()
+        }
+        None => String::from("legacy"),
+    };
+
+    if instance.config.manifest_url.is_some() {
+        phase(window_info, "Fichiers de l'instance...");
+        gather(instance.sync_files(advance).await, &mut missing)?;
+    }
+    let Some(java) = java.filter(|_| missing.is_empty()) else {
+        return Err(DownloadError::Missing(missing).into());
+    };
+
+    let natives_dir = version::natives_directory(&work_dir, &version.id);
+    version::clean_natives(&work_dir, &version.id, &natives_dir);
+    version::extract_natives(&plan.natives, &libraries_dir, &natives_dir)?;
+
+    let separator = if cfg!(target_os = "windows") { ";" } else { ":" };
+    let mut classpath = plan.classpath_in(&libraries_dir);
+    classpath.push(client_path);
+    let classpath = classpath.iter().map(|p| lossy(p)).collect::<Vec<_>>().join(separator);
+    let heap = java::machine_heap(&config.memory);
+    let mut variables: Variables = [
+        ("auth_player_name", config.username.clone()),
+        ("auth_uuid", offline_uuid(&config.username)),
+        ("auth_access_token", String::from("0")),
+        ("auth_session", String::from("0")),
+        ("auth_xuid", String::from("0")),
+        ("clientid", String::new()),
+        ("user_type", String::from("legacy")),
+        ("user_properties", String::from("{}")),
+        ("version_name", version.id.clone()),
+        ("version_type", version.version_type.clone()),
+        ("game_directory", lossy(&game_dir)),
+        ("assets_root", lossy(&assets_dir)),
+        ("game_assets", lossy(&assets_dir.join("virtual").join("legacy"))),
+        ("assets_index_name", version.assets.clone().unwrap_or(assets_index)),
+        ("natives_directory", lossy(&natives_dir)),
+        ("library_directory", lossy(&libraries_dir)),
+        ("classpath_separator", String::from(separator)),
+        ("classpath", classpath),
+        ("launcher_name", String::from("minecraft-portable-launcher")),
+        ("launcher_version", String::from(env!("CARGO_PKG_VERSION"))),
+    ].into_iter().collect();
+    variables.extend(heap.variables());
+
+    let mut arguments = java::memory_arguments(&config.memory, Some(java_version.major_version), &variables);
+    arguments.extend(instance.config.jvm_args.iter().map(|a| version::substitute(a, &variables)));
+    arguments.extend(version.jvm_arguments(&platform, &features, &variables));
+    arguments.push(version.main_class.clone());
+    arguments.extend(version.game_arguments(&platform, &features, &variables));
+    Ok(Launcher {
+        program: lossy(&java),
+        arguments,
+        working_dir: Some(game_dir),
+    })
+}
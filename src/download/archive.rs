@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use super::{file_to_hash, DownloadError, File, HashAlgorithm};
+use crate::{cache, log};
+
+//what the launcher remembers of each extracted archive, in <root>/.archives/<archive hash>.json
+pub const RECORDS_DIR: &str = ".archives";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+
+//many small files shipped as one download, extracted into target
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Archive {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    pub size: u64,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_sha1")]
+    pub algorithm: HashAlgorithm,
+    pub format: ArchiveFormat,
+    //directory of the root the entries go to, the root itself when empty
+    #[serde(default)]
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Extracted {
+    //relative to the root, "/" separated
+    pub path: String,
+    pub size: u64,
+    pub sha1: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ArchiveRecord {
+    pub files: Vec<Extracted>,
+}
+
+fn archive_error<E: std::fmt::Display>(e: E) -> DownloadError {
+    DownloadError::Archive(e.to_string())
+}
+
+//the path of an entry below target, None when it would land outside of it
+pub(super) fn entry_path(target: &str, name: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?.to_string()),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    let base = parts.len();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?.to_string()),
+            Component::CurDir => {}
+            Component::ParentDir if parts.len() > base => {
+                parts.pop();
+            }
+            _ => return None,
+        }
+    }
+    (parts.len() > base).then(|| parts.join("/"))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    //only the permission bits, never setuid and the like
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+impl ArchiveRecord {
+    fn path(root: &str, hash: &str) -> PathBuf {
+        Path::new(root).join(RECORDS_DIR).join(format!("{}.json", hash))
+    }
+
+    pub fn load(root: &str, hash: &str) -> Option<ArchiveRecord> {
+        let bytes = std::fs::read(Self::path(root, hash)).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    //the hashes of every archive extracted in root
+    pub fn list(root: &str) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(Path::new(root).join(RECORDS_DIR)) else {
+            return Vec::new();
+        };
+        entries.filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().to_str()?.strip_suffix(".json").map(str::to_string))
+            .collect()
+    }
+
+    fn save(&self, root: &str, hash: &str) -> Result<(), DownloadError> {
+        let path = Self::path(root, hash);
+        std::fs::create_dir_all(path.parent().expect("record has a parent")).map_err(DownloadError::Io)?;
+        let bytes = serde_json::to_vec(self).map_err(|e| DownloadError::Io(e.into()))?;
+        std::fs::write(path, bytes).map_err(DownloadError::Io)
+    }
+
+    pub fn remove(root: &str, hash: &str) {
+        let _ = std::fs::remove_file(Self::path(root, hash));
+    }
+
+    //every extracted file still there, unchanged. The ones the manifest lists itself are its business
+    fn is_intact(&self, root: &str, overridden: &HashSet<String>) -> bool {
+        self.files.iter().filter(|f| !overridden.contains(&f.path)).all(|f| {
+            let path = Path::new(root).join(&f.path);
+            path.metadata().is_ok_and(|m| m.len() == f.size)
+                && file_to_hash::<Sha1, _>(&path).is_ok_and(|hash| hex::encode(hash) == f.sha1)
+        })
+    }
+
+    fn add(&mut self, root: &str, path: String) -> Result<(), DownloadError> {
+        let full = Path::new(root).join(&path);
+        let size = full.metadata().map_err(DownloadError::Io)?.len();
+        let sha1 = hex::encode(file_to_hash::<Sha1, _>(&full)?);
+        self.files.push(Extracted { path, size, sha1 });
+        Ok(())
+    }
+}
+
+fn create_file(root: &str, path: &str, mut content: impl Read, mode: Option<u32>) -> Result<(), DownloadError> {
+    let full = Path::new(root).join(path);
+    std::fs::create_dir_all(full.parent().expect("entry has a parent")).map_err(DownloadError::Io)?;
+    let mut file = cache::create_file(&full).map_err(DownloadError::Io)?;
+    std::io::copy(&mut content, &mut file).map_err(DownloadError::Io)?;
+    if let Some(mode) = mode {
+        set_mode(&full, mode).map_err(DownloadError::Io)?;
+    }
+    Ok(())
+}
+
+impl Archive {
+    //download and extract the archive unless its last extraction is still intact. The files it held
+    //are returned in a record, also kept in root. Entries at an overridden path, "/" separated, are left
+    //to the manifest's own files
+    pub async fn install(&self, root: &str, overridden: &HashSet<String>) -> Result<ArchiveRecord, DownloadError> {
+        if let Some(record) = ArchiveRecord::load(root, &self.hash).filter(|r| r.is_intact(root, overridden)) {
+            return Ok(record);
+        }
+        if !self.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(DownloadError::Archive(format!("invalid hash for {}", self.url)));
+        }
+        //next to the cache when there is one, the archive is then hardlinked into it
+        let staging = match cache::shared() {
+            Some(store) => store.staging_dir(),
+            None => tempfile::tempdir(),
+        }.map_err(DownloadError::Io)?;
+        let download = self.as_file();
+        download.ensure(&staging.path().to_string_lossy()).await.map_err(|e| match e {
+            DownloadError::Missing(_) => DownloadError::missing(&self.url),
+            e => e,
+        })?;
+        let path = staging.path().join(&download.path);
+        let open = || std::fs::File::open(&path).map_err(DownloadError::Io);
+        //every name is checked before anything is written, a bad entry leaves the game directory untouched
+        let record = match self.format {
+            ArchiveFormat::Zip => {
+                self.check_zip(open()?)?;
+                self.extract_zip(open()?, root, overridden)?
+            }
+            ArchiveFormat::TarZst => {
+                self.check_tar_zst(open()?)?;
+                self.extract_tar_zst(open()?, root, overridden)?
+            }
+        };
+        record.save(root, &self.hash)?;
+        log!("INFO", self.url, "{} files extracted into {}", record.files.len(), Path::new(root).join(&self.target).display());
+        Ok(record)
+    }
+
+    //the archive itself, downloaded like any file of the manifest
+    pub fn as_file(&self) -> File {
+        File {
+            url: self.url.clone(),
+            mirrors: self.mirrors.clone(),
+            size: self.size,
+            hash: self.hash.clone(),
+            algorithm: self.algorithm,
+            path: self.hash.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn entry(&self, name: &Path) -> Result<String, DownloadError> {
+        entry_path(&self.target, name)
+            .ok_or_else(|| DownloadError::Archive(format!("{} of {} leaves its target directory", name.display(), self.url)))
+    }
+
+    fn check_zip(&self, archive: std::fs::File) -> Result<(), DownloadError> {
+        let mut archive = zip::ZipArchive::new(archive).map_err(archive_error)?;
+        for i in 0..archive.len() {
+            self.entry(Path::new(archive.by_index_raw(i).map_err(archive_error)?.name()))?;
+        }
+        Ok(())
+    }
+
+    fn check_tar_zst(&self, archive: std::fs::File) -> Result<(), DownloadError> {
+        let decoder = zstd::stream::read::Decoder::new(archive).map_err(DownloadError::Io)?;
+        for entry in tar::Archive::new(decoder).entries().map_err(archive_error)? {
+            self.entry(&entry.map_err(archive_error)?.path().map_err(archive_error)?)?;
+        }
+        Ok(())
+    }
+
+    fn extract_zip(&self, archive: std::fs::File, root: &str, overridden: &HashSet<String>) -> Result<ArchiveRecord, DownloadError> {
+        let mut archive = zip::ZipArchive::new(archive).map_err(archive_error)?;
+        let mut record = ArchiveRecord::default();
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).map_err(archive_error)?;
+            let path = self.entry(Path::new(entry.name()))?;
+            let mode = entry.unix_mode();
+            if entry.is_dir() {
+                std::fs::create_dir_all(Path::new(root).join(&path)).map_err(DownloadError::Io)?;
+                continue;
+            }
+            //S_IFLNK: the entry holds the target of a symlink
+            if mode.is_some_and(|m| m & 0o170000 == 0o120000) {
+                log!("WARN", self.url, "symlink {} skipped", path);
+                continue;
+            }
+            if overridden.contains(&path) {
+                continue;
+            }
+            create_file(root, &path, entry, mode)?;
+            record.add(root, path)?;
+        }
+        Ok(record)
+    }
+
+    fn extract_tar_zst(&self, archive: std::fs::File, root: &str, overridden: &HashSet<String>) -> Result<ArchiveRecord, DownloadError> {
+        let decoder = zstd::stream::read::Decoder::new(archive).map_err(DownloadError::Io)?;
+        let mut archive = tar::Archive::new(decoder);
+        let mut record = ArchiveRecord::default();
+        for entry in archive.entries().map_err(archive_error)? {
+            let entry = entry.map_err(archive_error)?;
+            let path = self.entry(&entry.path().map_err(archive_error)?)?;
+            let mode = entry.header().mode().ok();
+            match entry.header().entry_type() {
+                tar::EntryType::Directory => std::fs::create_dir_all(Path::new(root).join(&path)).map_err(DownloadError::Io)?,
+                tar::EntryType::Regular | tar::EntryType::Continuous if overridden.contains(&path) => {}
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    create_file(root, &path, entry, mode)?;
+                    record.add(root, path)?;
+                }
+                other => log!("WARN", self.url, "{} skipped, {:?} entries aren't extracted", path, other),
+            }
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_entries_inside_the_target() {
+        assert_eq!(entry_path("resourcepacks", Path::new("pack/./a.png")).as_deref(), Some("resourcepacks/pack/a.png"));
+        assert_eq!(entry_path("", Path::new("config/x.toml")).as_deref(), Some("config/x.toml"));
+        assert_eq!(entry_path("shaders", Path::new("a/../b")).as_deref(), Some("shaders/b"));
+        assert_eq!(entry_path("shaders", Path::new("../options.txt")), None);
+        assert_eq!(entry_path("", Path::new("/etc/passwd")), None);
+        assert_eq!(entry_path("../mods", Path::new("x.jar")), None);
+    }
+}
@@ -1,26 +1,227 @@
-use serde::Deserialize;
-use tokio::io::{AsyncWriteExt, AsyncReadExt};
-use sha1::Sha1;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
 use tokio::fs;
-use std::path::{Path, PathBuf};
-use super::{file_to_hash, DownloadError};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use futures_util::StreamExt;
+use super::archive::entry_path;
+use super::{download_sized, Archive, ArchiveRecord, Capped, Compression, Decompressor, DownloadError, HashAlgorithm, RECORDS_DIR};
+use crate::{cache, env, log, network, peer};
+use crate::version::Platform;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct File {
     pub url: String,
+    //tried in order before url, the mirrors configured in launcher.json come before each of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
     pub size: u64,
     pub hash: String,
     pub path: String,
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_sha1")]
+    pub algorithm: HashAlgorithm,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub executable: bool,
+    //written once then left to the user, like options.txt
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub only_if_missing: bool,
+    //"windows", "osx" or "linux", every system when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+    //of the payloads at url and the mirrors, size and hash are the ones of the decompressed file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    //from the previous versions, tried before downloading the whole file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<Patch>,
+}
+
+//`zstd --patch-from=<previous> <file>` or bsdiff output
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchFormat {
+    #[default]
+    Zstd,
+    Bsdiff,
+}
+
+//--patch-from sets the window to the size of the previous version
+const PATCH_WINDOW_LOG: u32 = if cfg!(target_pointer_width = "64") { 31 } else { 30 };
+
+impl PatchFormat {
+    fn apply(self, base: &[u8], delta: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut patched = Vec::new();
+        match self {
+            PatchFormat::Zstd => {
+                let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(delta, base)?;
+                decoder.window_log_max(PATCH_WINDOW_LOG)?;
+                std::io::copy(&mut decoder, &mut patched)?;
+            }
+            PatchFormat::Bsdiff => bsdiff::patch(base, &mut &delta[..], &mut patched)?,
+        }
+        Ok(patched)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Patch {
+    //hash of the version it applies to, with the algorithm of the file
+    pub from: String,
+    pub url: String,
+    #[serde(default)]
+    pub format: PatchFormat,
+}
+
+//kept even when empty, a mod may expect it
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Directory {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+}
+
+//target is relative to the directory of the link and must stay inside the root
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Link {
+    pub path: String,
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+}
+
+fn for_this_os(os: &[String], platform: &str) -> bool {
+    os.is_empty() || os.iter().any(|o| o == platform)
+}
+
+//relative paths that don't leave the directory they are resolved in
+fn is_contained(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn contained_path(root: &str, path: &str) -> Result<PathBuf, DownloadError> {
+    if !is_contained(Path::new(path)) {
+        return Err(DownloadError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} leaves the game directory", path))));
+    }
+    Ok(Path::new(root).join(path))
+}
+
+//chmod applies to every link of the inode, a file shared with the cache is detached first
+#[cfg(unix)]
+pub fn set_executable(path: &Path) -> Result<(), DownloadError> {
+    use std::os::unix::fs::PermissionsExt;
+    if is_shared(path) {
+        detach(path)?;
+    }
+    let mut permissions = std::fs::metadata(path).map_err(DownloadError::Io)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    std::fs::set_permissions(path, permissions).map_err(DownloadError::Io)
+}
+
+#[cfg(not(unix))]
+pub fn set_executable(_path: &Path) -> Result<(), DownloadError> {
+    Ok(())
+}
+
+//a symlink at path to target, relative to its directory. Left alone when it already points there
+pub fn make_link(path: &Path, target: &str) -> Result<(), DownloadError> {
+    if std::fs::read_link(path).is_ok_and(|current| current == Path::new(target)) {
+        return Ok(());
+    }
+    let _ = std::fs::remove_file(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(DownloadError::Io)?;
+    }
+    symlink(path, target)
+}
+
+#[cfg(unix)]
+fn symlink(path: &Path, target: &str) -> Result<(), DownloadError> {
+    std::os::unix::fs::symlink(target, path).map_err(DownloadError::Io)
+}
+
+//symlinks need the developer mode on windows, copy the target when they are refused
+#[cfg(not(unix))]
+fn symlink(path: &Path, target: &str) -> Result<(), DownloadError> {
+    let source = path.parent().unwrap_or(Path::new("")).join(target);
+    let linked = if source.is_dir() {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    };
+    match linked {
+        Ok(()) => Ok(()),
+        Err(e) if source.is_file() => {
+            log!("WARN", path.display(), "symlink refused, copying {}: {}", target, e);
+            std::fs::copy(source, path).map(|_| ()).map_err(DownloadError::Io)
+        }
+        Err(e) => Err(DownloadError::Io(e)),
+    }
+}
+
+//whether the file is one of several links to its inode, like the objects of the shared cache
+#[cfg(unix)]
+fn is_shared(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).is_ok_and(|m| m.nlink() > 1)
+}
+
+//replace the file by a copy of its own, so changing it leaves its other links alone
+fn detach(path: &Path) -> Result<(), DownloadError> {
+    let mut copy = path.as_os_str().to_owned();
+    copy.push(".detached");
+    std::fs::copy(path, &copy).map_err(DownloadError::Io)?;
+    std::fs::rename(&copy, path).map_err(DownloadError::Io)
+}
+
+impl Link {
+    fn create(&self, root: &str) -> Result<(), DownloadError> {
+        let path = contained_path(root, &self.path)?;
+        let relative_parent = Path::new(&self.path).parent().unwrap_or(Path::new(""));
+        if !is_contained(&relative_parent.join(&self.target)) {
+            return Err(DownloadError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} points outside the game directory", self.path))));
+        }
+        make_link(&path, &self.target)
+    }
 }
 
 impl File {
-    pub async fn download(&self, root: &str) -> Result<(), DownloadError> {
-        let resp = reqwest::get(&self.url).await.map_err(DownloadError::Reqwest)?;
-        let bytes = resp.bytes().await.map_err(DownloadError::Reqwest)?;
+    async fn download(&self, url: &str, compression: Option<Compression>, root: &str) -> Result<(), DownloadError> {
+        //indexes are json, worth asking the server to compress
+        let json = self.path.ends_with(".json");
+        //plain payloads, like the runtimes' files, may be fetched in ranges
+        if compression.is_none() && !json {
+            return download_sized(url, self.fullpath(root), self.size, |_| false).await;
+        }
+        let _connection = network::connection(url).await;
+        let resp = if json { network::get_json(url).await } else { network::get(url).await };
+        let resp = resp.and_then(|r| r.error_for_status()).map_err(DownloadError::Reqwest)?;
         let path = std::path::PathBuf::from(root).join(&self.path);
         self.create_folder(root).await?;
-        let mut file = fs::File::create(path).await.map_err(DownloadError::Io)?;
-        file.write_all(&bytes).await.map_err(DownloadError::Io)?;
+        let file = cache::create_file(&path).map_err(DownloadError::Io)?;
+        let output = Capped::new(std::io::BufWriter::new(file), self.size);
+        let mut output = Decompressor::new(compression, output).map_err(DownloadError::Io)?;
+        //corrupt or oversized payloads are the server's fault, the next url is tried
+        let payload_error = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::InvalidData => {
+                log!("WARN", url, "{}", e);
+                DownloadError::HashMismatch(self.path.clone())
+            }
+            _ => DownloadError::Io(e),
+        };
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = network::next_chunk(&mut stream).await {
+            output.write_all(&chunk?).map_err(payload_error)?;
+        }
+        output.finish().map_err(payload_error)?;
         Ok(())
     }
 
@@ -33,10 +234,129 @@ impl File {
         Ok(hash == self.hash)
     }
 
-    fn file_hash(&self, root: &str) -> Result<String, DownloadError> {
+    //download the file unless a verified copy is already present, or can be taken from the shared cache
+    pub async fn ensure(&self, root: &str) -> Result<(), DownloadError> {
         let path = self.fullpath(root);
-        let hash = file_to_hash::<Sha1, _>(path)?;
-        Ok(hex::encode(&hash))
+        if path.exists() && self.verify(root).await? {
+            return Ok(());
+        }
+        let store = cache::shared();
+        if let Some(store) = store {
+            if store.restore(&self.hash, &path) {
+                if self.verify(root).await? {
+                    return Ok(());
+                }
+                store.forget(&self.hash);
+            }
+        }
+        if network::is_offline() {
+            return Err(DownloadError::missing(path));
+        }
+        if path.exists() {
+            match self.patch(root).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    if let Some(store) = store {
+                        store.insert(&path, &self.hash);
+                    }
+                    return Ok(());
+                }
+                Err(e) => log!("WARN", self.path, "patch not applied, downloading the whole file: {}", e),
+            }
+        }
+        let urls = self.mirrors.iter().chain(std::iter::once(&self.url)).cloned().collect::<Vec<_>>();
+        let mut last_error = DownloadError::NotFound(self.path.clone());
+        //the peers' copies are checked like any other, they serve them decompressed
+        let sources = peer::urls(&self.hash).into_iter().map(|url| (url, None))
+            .chain(network::candidates(&urls).into_iter().map(|url| (url, self.compression)));
+        for (url, compression) in sources {
+            let result = match self.download(&url, compression, root).await {
+                Ok(()) if self.verify(root).await? => Ok(()),
+                Ok(()) => Err(DownloadError::HashMismatch(self.path.clone())),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    if let Some(store) = store {
+                        store.insert(&path, &self.hash);
+                    }
+                    return Ok(());
+                }
+                //a peer or a mirror without this one file, or with another version of it, isn't unhealthy
+                Err(e) if e.is_not_found() => {
+                    log!("INFO", url, "{}", e);
+                    last_error = e;
+                }
+                Err(e @ DownloadError::HashMismatch(_)) => {
+                    log!("WARN", url, "{}", e);
+                    last_error = e;
+                }
+                Err(e) if e.is_remote() => {
+                    log!("WARN", url, "{}", e);
+                    network::mark_unhealthy(&url);
+                    last_error = e;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error)
+    }
+
+    //update the local copy with the patch made from it, if the manifest has one. Ok(false) when there is none
+    async fn patch(&self, root: &str) -> Result<bool, DownloadError> {
+        if self.patches.is_empty() {
+            return Ok(false);
+        }
+        let local = self.file_hash(root)?;
+        let Some(patch) = self.patches.iter().find(|p| p.from == local) else {
+            return Ok(false);
+        };
+        let _connection = network::connection(&patch.url).await;
+        let resp = network::get(&patch.url).await.and_then(|r| r.error_for_status()).map_err(DownloadError::Reqwest)?;
+        let delta = network::body(resp).await?;
+        let path = self.fullpath(root);
+        let base = fs::read(&path).await.map_err(DownloadError::Io)?;
+        let patched = patch.format.apply(&base, &delta).map_err(DownloadError::Io)?;
+        cache::create_file(&path).and_then(|mut file| std::io::Write::write_all(&mut file, &patched)).map_err(DownloadError::Io)?;
+        if !self.verify(root).await? {
+            return Err(DownloadError::HashMismatch(self.path.clone()));
+        }
+        Ok(true)
+    }
+
+    //the same download named after its hash, for a staging directory
+    fn staged(&self) -> File {
+        File {
+            url: self.url.clone(),
+            mirrors: self.mirrors.clone(),
+            size: self.size,
+            hash: self.hash.clone(),
+            algorithm: self.algorithm,
+            compression: self.compression,
+            path: self.hash.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn file_hash(&self, root: &str) -> Result<String, DownloadError> {
+        self.algorithm.file_hash(self.fullpath(root))
+    }
+
+    //ensure, with the manifest options: left alone when only_if_missing, made executable when asked
+    async fn install(&self, root: &str) -> Result<(), DownloadError> {
+        let path = contained_path(root, &self.path)?;
+        if self.only_if_missing && path.exists() {
+            return Ok(());
+        }
+        self.ensure(root).await?;
+        //the game rewrites only_if_missing files in place, that must not reach the cached object
+        if self.only_if_missing {
+            detach(&path)?;
+        }
+        if self.executable {
+            set_executable(&path)?;
+        }
+        Ok(())
     }
 
     //create folder if doesn't exists
@@ -56,17 +376,92 @@ impl File {
         std::path::PathBuf::from(root).join(&self.path)
     }
 }
-#[derive(Deserialize, Debug)]
-#[serde(transparent)]
+#[derive(Deserialize, Debug, Default)]
+#[serde(from = "ManifestFormat")]
 pub struct Files{
     pub files: Vec<File>,
+    //the fields below only exist in the object form
+    //set by `manifest generate --version`
+    pub version: Option<u64>,
+    //shown when a newer version is synced
+    pub changelog: Option<String>,
+    pub directories: Vec<Directory>,
+    pub links: Vec<Link>,
+    pub archives: Vec<Archive>,
 }
 // type Files = Vec<File>;
 
+//the original bare array, or an object with the version, changelog, directories, links and archives
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestFormat {
+    Plain(Vec<File>),
+    Versioned {
+        #[serde(default)]
+        version: Option<u64>,
+        #[serde(default)]
+        changelog: Option<String>,
+        files: Vec<File>,
+        #[serde(default)]
+        directories: Vec<Directory>,
+        #[serde(default)]
+        links: Vec<Link>,
+        #[serde(default)]
+        archives: Vec<Archive>,
+    },
+}
+
+impl From<ManifestFormat> for Files {
+    fn from(format: ManifestFormat) -> Self {
+        match format {
+            ManifestFormat::Plain(files) => files.into(),
+            ManifestFormat::Versioned { version, changelog, files, directories, links, archives } => {
+                Self { files, version, changelog, directories, links, archives }
+            }
+        }
+    }
+}
+
+impl Serialize for Files {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let plain = self.version.is_none() && self.changelog.is_none();
+        if plain && self.directories.is_empty() && self.links.is_empty() && self.archives.is_empty() {
+            return self.files.serialize(serializer);
+        }
+        let mut manifest = serializer.serialize_struct("Files", 6)?;
+        match &self.version {
+            Some(version) => manifest.serialize_field("version", version)?,
+            None => manifest.skip_field("version")?,
+        }
+        match &self.changelog {
+            Some(changelog) => manifest.serialize_field("changelog", changelog)?,
+            None => manifest.skip_field("changelog")?,
+        }
+        manifest.serialize_field("files", &self.files)?;
+        if self.directories.is_empty() {
+            manifest.skip_field("directories")?;
+        } else {
+            manifest.serialize_field("directories", &self.directories)?;
+        }
+        if self.links.is_empty() {
+            manifest.skip_field("links")?;
+        } else {
+            manifest.serialize_field("links", &self.links)?;
+        }
+        if self.archives.is_empty() {
+            manifest.skip_field("archives")?;
+        } else {
+            manifest.serialize_field("archives", &self.archives)?;
+        }
+        manifest.end()
+    }
+}
+
 impl Files {
     
     pub async fn from_url(url: &str) -> Result<Files, reqwest::Error> {
-        Self::from_response(reqwest::get(url).await?).await
+        Self::from_response(network::get_json(url).await?).await
     }
     pub async fn from_response(resp: reqwest::Response) -> Result<Files, reqwest::Error> {
         Ok(resp.json::<Files>().await?)
@@ -80,6 +475,117 @@ impl Files {
     pub fn into_iter(self) -> std::vec::IntoIter<File> {
         self.files.into_iter()
     }
+    //extract the archives and download every missing or corrupted file of this system, reporting progress
+    //in percent of the total size, then create the directories and links
+    pub async fn sync<F>(&self, root: &str, callback_send_delta: F) -> Result<(), DownloadError>
+    where
+        F: Fn(u32) -> bool
+    {
+        let platform = Platform::current().name;
+        let archives = self.archives.iter().filter(|a| for_this_os(&a.os, platform)).collect::<Vec<_>>();
+        let files = self.files.iter().filter(|f| for_this_os(&f.os, platform)).collect::<Vec<_>>();
+        let total: u64 = archives.iter().map(|a| a.size).chain(files.iter().map(|f| f.size)).sum();
+        let mut current_bytes = 0;
+        let mut current_percent = 0;
+        let mut advance = |size: u64| {
+            current_bytes += size;
+            if total == 0 {
+                return Ok(());
+            }
+            let next_percent = current_bytes * 100 / total;
+            if next_percent > current_percent {
+                if callback_send_delta((next_percent - current_percent) as u32) {
+                    return Err(DownloadError::DownloadStopped);
+                }
+                current_percent = next_percent;
+            }
+            Ok(())
+        };
+        //offline, every missing file is listed instead of stopping at the first one
+        let mut missing = Vec::new();
+        //the files listed in the manifest win over the archives' ones, which are neither extracted nor checked
+        let overridden = files.iter().filter_map(|f| entry_path("", Path::new(&f.path))).collect::<HashSet<_>>();
+        let mut records = Vec::new();
+        for archive in &archives {
+            match archive.install(root, &overridden).await {
+                Err(DownloadError::Missing(paths)) => missing.extend(paths),
+                record => records.push(record?),
+            }
+            advance(archive.size)?;
+        }
+        let mut stream = futures_util::stream::iter(files.into_iter().map(|f| async move {
+            f.install(root).await.map(|_| f.size)
+        })).buffer_unordered(env::DOWNLOAD_CONCURRENCY);
+        while let Some(size) = stream.next().await {
+            match size {
+                Err(DownloadError::Missing(paths)) => missing.extend(paths),
+                size => advance(size?)?,
+            }
+        }
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(DownloadError::Missing(missing));
+        }
+        self.forget_archives(root, &archives, &records);
+        for directory in self.directories.iter().filter(|d| for_this_os(&d.os, platform)) {
+            fs::create_dir_all(contained_path(root, &directory.path)?).await.map_err(DownloadError::Io)?;
+        }
+        for link in self.links.iter().filter(|l| for_this_os(&l.os, platform)) {
+            link.create(root)?;
+        }
+        Ok(())
+    }
+    //the files of the archives no longer in the manifest are removed, unless an archive or the manifest
+    //still has them
+    fn forget_archives(&self, root: &str, archives: &[&Archive], records: &[ArchiveRecord]) {
+        let kept = records.iter().flat_map(|r| r.files.iter().map(|f| f.path.as_str()))
+            .chain(self.files.iter().map(|f| f.path.as_str()))
+            .collect::<HashSet<_>>();
+        for hash in ArchiveRecord::list(root) {
+            if archives.iter().any(|a| a.hash == hash) {
+                continue;
+            }
+            if let Some(record) = ArchiveRecord::load(root, &hash) {
+                for file in record.files.iter().filter(|f| !kept.contains(f.path.as_str())) {
+                    if let Err(e) = std::fs::remove_file(Path::new(root).join(&file.path)) {
+                        log!("WARN", file.path, "not removed: {}", e);
+                    }
+                }
+            }
+            ArchiveRecord::remove(root, &hash);
+        }
+    }
+    //download the files the shared cache lacks into it, leaving every game directory alone: the next sync
+    //only links them. They are kept until the next `cache gc`
+    pub async fn prefetch(&self) -> Result<usize, DownloadError> {
+        match cache::shared() {
+            Some(store) => self.prefetch_into(store).await,
+            None => Ok(0),
+        }
+    }
+    async fn prefetch_into(&self, store: &cache::Store) -> Result<usize, DownloadError> {
+        let staging = store.staging_dir().map_err(DownloadError::Io)?;
+        let root = staging.path().to_string_lossy().into_owned();
+        let platform = Platform::current().name;
+        let archives = self.archives.iter().filter(|a| for_this_os(&a.os, platform)).map(Archive::as_file).collect::<Vec<_>>();
+        //staged under their hash, never under a path of the manifest: nothing is written outside of staging
+        let mut missing = self.files.iter()
+            .filter(|f| for_this_os(&f.os, platform))
+            .chain(archives.iter())
+            .filter(|f| f.hash.len() > 2 && f.hash.chars().all(|c| c.is_ascii_hexdigit()))
+            .filter(|f| store.lookup(&f.hash).is_none())
+            .map(File::staged)
+            .collect::<Vec<_>>();
+        missing.sort_by(|a, b| a.hash.cmp(&b.hash));
+        missing.dedup_by(|a, b| a.hash == b.hash);
+        //collected first: the future is spawned and a closure in the stream wouldn't be Send
+        let downloads = missing.iter().map(|f| f.ensure(&root)).collect::<Vec<_>>();
+        let mut stream = futures_util::stream::iter(downloads).buffer_unordered(env::DOWNLOAD_CONCURRENCY);
+        while let Some(result) = stream.next().await {
+            result?;
+        }
+        Ok(missing.len())
+    }
     fn make_list_files(parent: &Path) -> Vec<PathBuf> {
         let mut v = Vec::new();
         if !parent.exists() {
@@ -94,7 +600,8 @@ impl Files {
                 },
             };
             let path = entry.path();
-            if path.is_dir() {
+            //a link is listed itself, never followed
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
                 let new_path = parent.join(path.file_name().expect("Error while getting directory name"));
                 v.extend(Self::make_list_files(&new_path));
             } else {
@@ -105,7 +612,15 @@ impl Files {
     }
     pub fn remove_old_files<P: AsRef<Path>>(&self, root: P) {
         let files_distant = {
-            let mut v = self.files.iter().map(|f| root.as_ref().join(PathBuf::from(f.path.as_str()))).collect::<Vec<_>>();
+            //the files extracted from the archives are managed as well, with their records
+            let root_str = root.as_ref().to_string_lossy();
+            let records = self.archives.iter().filter_map(|a| Some((a, ArchiveRecord::load(&root_str, &a.hash)?))).collect::<Vec<_>>();
+            let mut v = self.files.iter().map(|f| f.path.clone())
+                .chain(self.links.iter().map(|l| l.path.clone()))
+                .chain(records.iter().flat_map(|(_, r)| r.files.iter().map(|f| f.path.clone())))
+                .chain(records.iter().map(|(a, _)| format!("{}/{}.json", RECORDS_DIR, a.hash)))
+                .map(|path| root.as_ref().join(path))
+                .collect::<Vec<_>>();
             v.sort();
             v
         };
@@ -114,25 +629,30 @@ impl Files {
             v.sort();
             v
         };
-        println!("files_distant: {:#?}", files_distant);
-        println!("files_local: {:#?}", files_local);
-        // return;
+        let remove = |path: &Path| {
+            log!("INFO", path.display(), "no longer in the manifest, removed");
+            if let Err(e) = std::fs::remove_file(path) {
+                log!("WARN", path.display(), "not removed: {}", e);
+            }
+        };
         let (mut file_distant_iter, mut file_local_iter) = (files_distant.into_iter(), files_local.into_iter());
         let (mut file_distant, mut file_local) = (file_distant_iter.next(), file_local_iter.next());
         loop {
             match (&mut file_distant, &mut file_local) {
-                (Some(d), Some(l)) => {
-                    if d != l {
-                        println!("Remove file: {}", l.to_str().unwrap_or("{unknown}"));
-                        std::fs::remove_file(l).unwrap();
-                    } else {
+                //both sorted: a distant path behind the local one isn't on disk, e.g. a file of another system
+                (Some(d), Some(l)) => match (*d).cmp(l) {
+                    std::cmp::Ordering::Less => file_distant = file_distant_iter.next(),
+                    std::cmp::Ordering::Equal => {
                         file_distant = file_distant_iter.next();
+                        file_local = file_local_iter.next();
                     }
-                    file_local = file_local_iter.next();
-                }
+                    std::cmp::Ordering::Greater => {
+                        remove(l);
+                        file_local = file_local_iter.next();
+                    }
+                },
                 (None, Some(l)) => {
-                    println!("Remove file: {}", l.to_str().unwrap_or("{unknown}"));
-                    std::fs::remove_file(l).unwrap();
+                    remove(l);
                     file_local = file_local_iter.next();
                 }
                 _ => break,
@@ -147,5 +667,347 @@ impl From<Files> for Vec<File> {
     }
 }
 
+impl From<Vec<File>> for Files {
+    fn from(files: Vec<File>) -> Self {
+        Self { files, ..Default::default() }
+    }
+}
+
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::ArchiveFormat;
+    use sha1::{Digest, Sha1};
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_url() {
+        let mirror = crate::test_server::TestServer::start(vec![("/corrupted/x.jar", b"corrupted".to_vec())]).await;
+        let server = crate::test_server::TestServer::start(vec![("/origin/x.jar", b"content".to_vec())]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let file = File {
+            url: server.url("/origin/x.jar"),
+            mirrors: vec![server.url("/missing/x.jar"), mirror.url("/corrupted/x.jar")],
+            size: 7,
+            hash: hex::encode(Sha1::digest(b"content")),
+            path: String::from("mods/x.jar"),
+            ..Default::default()
+        };
+
+        file.ensure(&dir.path().to_string_lossy()).await.ok().unwrap();
+
+        assert_eq!(std::fs::read(dir.path().join("mods/x.jar")).unwrap(), b"content");
+        //neither the 404 nor the other version demoted the mirror
+        let candidates = network::candidates(&[mirror.url("/y.jar"), server.url("/y.jar")]);
+        assert_eq!(candidates[0], mirror.url("/y.jar"));
+    }
+
+    #[tokio::test]
+    async fn patches_the_previous_version() {
+        let (old, new) = (b"version 1 of the mod".repeat(100), b"version 2 of the mod".repeat(100));
+        let mut zstd_patch = Vec::new();
+        let mut encoder = zstd::stream::write::Encoder::with_ref_prefix(&mut zstd_patch, 3, &old).unwrap();
+        std::io::Write::write_all(&mut encoder, &new).unwrap();
+        encoder.finish().unwrap();
+        let mut bsdiff_patch = Vec::new();
+        bsdiff::diff(&old, &new, &mut bsdiff_patch).unwrap();
+        let server = crate::test_server::TestServer::start(vec![
+            ("/x.jar.zst", zstd_patch),
+            ("/x.jar.bsdiff", bsdiff_patch),
+            ("/broken.bsdiff", b"not a patch".to_vec()),
+            ("/x.jar", new.clone()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy();
+        let old_hash = hex::encode(Sha1::digest(&old));
+
+        for (url, format) in [("/x.jar.zst", PatchFormat::Zstd), ("/x.jar.bsdiff", PatchFormat::Bsdiff), ("/broken.bsdiff", PatchFormat::Bsdiff)] {
+            std::fs::write(dir.path().join("x.jar"), &old).unwrap();
+            let file = File {
+                url: server.url("/x.jar"),
+                size: new.len() as u64,
+                hash: hex::encode(Sha1::digest(&new)),
+                path: String::from("x.jar"),
+                patches: vec![Patch { from: old_hash.clone(), url: server.url(url), format }],
+                ..Default::default()
+            };
+            if url != "/broken.bsdiff" {
+                assert!(file.patch(&root).await.ok().unwrap());
+            }
+            std::fs::write(dir.path().join("x.jar"), &old).unwrap();
+            file.ensure(&root).await.ok().unwrap();
+            assert!(std::fs::read(dir.path().join("x.jar")).unwrap() == new);
+        }
+    }
+
+    #[tokio::test]
+    async fn decompresses_the_payloads() {
+        use std::io::Write;
+        let content = b"maxFps:120\nfov:70\n".repeat(50);
+        let zstd_payload = zstd::encode_all(&content[..], 3).unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&content).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&content).unwrap();
+        let server = crate::test_server::TestServer::start(vec![
+            ("/options.txt.zst", zstd_payload.clone()),
+            ("/options.txt.gz", gzip.finish().unwrap()),
+            ("/options.txt.xz", xz.finish().unwrap()),
+            ("/truncated.zst", zstd_payload[..zstd_payload.len() / 2].to_vec()),
+            ("/bomb.zst", zstd::encode_all(&vec![0u8; 64 << 20][..], 19).unwrap()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy();
+
+        for (url, compression) in [("/options.txt.zst", Compression::Zstd), ("/options.txt.gz", Compression::Gzip), ("/options.txt.xz", Compression::Xz)] {
+            let file = File {
+                url: server.url(url),
+                size: content.len() as u64,
+                hash: hex::encode(Sha1::digest(&content)),
+                path: format!("{}.txt", url.rsplit('.').next().unwrap()),
+                compression: Some(compression),
+                ..Default::default()
+            };
+            file.ensure(&root).await.ok().unwrap();
+            assert!(std::fs::read(file.fullpath(&root)).unwrap() == content);
+        }
+        let truncated = File {
+            url: server.url("/truncated.zst"),
+            size: content.len() as u64,
+            hash: hex::encode(Sha1::digest(&content)),
+            path: String::from("truncated.txt"),
+            compression: Some(Compression::Zstd),
+            ..Default::default()
+        };
+        assert!(truncated.ensure(&root).await.is_err());
+        let bomb = File {
+            url: server.url("/bomb.zst"),
+            size: 1000,
+            hash: hex::encode(Sha1::digest([0u8; 1000])),
+            path: String::from("bomb.txt"),
+            compression: Some(Compression::Zstd),
+            ..Default::default()
+        };
+        assert!(matches!(bomb.ensure(&root).await, Err(DownloadError::HashMismatch(_))));
+        assert!(std::fs::metadata(bomb.fullpath(&root)).unwrap().len() <= 1000);
+    }
+
+    fn zip(entries: &[(&str, &[u8], u32)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content, mode) in entries {
+            let options = zip::write::FileOptions::default().unix_permissions(*mode);
+            writer.start_file(*name, options).unwrap();
+            std::io::Write::write_all(&mut writer, content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn archive(server: &crate::test_server::TestServer, url: &str, content: &[u8], format: ArchiveFormat, target: &str) -> Archive {
+        Archive {
+            url: server.url(url),
+            size: content.len() as u64,
+            hash: hex::encode(Sha1::digest(content)),
+            format,
+            target: String::from(target),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn extracts_and_manages_archives() {
+        let (v1, v2) = (zip(&[("a.png", b"a", 0o644), ("b.png", b"b", 0o644)]), zip(&[("a.png", b"a2", 0o644)]));
+        let evil = zip(&[("ok.png", b"ok", 0o644), ("../options.txt", b"evil", 0o644)]);
+        let mut tar = tar::Builder::new(zstd::stream::write::Encoder::new(Vec::new(), 3).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(9);
+        header.set_mode(0o755);
+        header.set_cksum();
+        tar.append_data(&mut header, "bin/run.sh", &b"#!/bin/sh"[..]).unwrap();
+        let tar_zst = tar.into_inner().unwrap().finish().unwrap();
+        let server = crate::test_server::TestServer::start(vec![
+            ("/v1.zip", v1.clone()),
+            ("/v2.zip", v2.clone()),
+            ("/evil.zip", evil.clone()),
+            ("/tools.tar.zst", tar_zst.clone()),
+            ("/a.png", b"mine".to_vec()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy();
+        let mut files = Files {
+            archives: vec![
+                archive(&server, "/v1.zip", &v1, ArchiveFormat::Zip, "resourcepacks/pack"),
+                archive(&server, "/tools.tar.zst", &tar_zst, ArchiveFormat::TarZst, ""),
+            ],
+            ..Default::default()
+        };
+
+        files.sync(&root, |_| false).await.ok().unwrap();
+        assert_eq!(std::fs::read(dir.path().join("resourcepacks/pack/b.png")).unwrap(), b"b");
+        assert_eq!(std::fs::read(dir.path().join("bin/run.sh")).unwrap(), b"#!/bin/sh");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(dir.path().join("bin/run.sh")).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+        std::fs::write(dir.path().join("stray.txt"), "x").unwrap();
+        files.remove_old_files(dir.path());
+        assert!(!dir.path().join("stray.txt").exists());
+        assert!(dir.path().join("resourcepacks/pack/a.png").exists());
+
+        files.archives[0] = archive(&server, "/v2.zip", &v2, ArchiveFormat::Zip, "resourcepacks/pack");
+        files.sync(&root, |_| false).await.ok().unwrap();
+        assert_eq!(std::fs::read(dir.path().join("resourcepacks/pack/a.png")).unwrap(), b"a2");
+        assert!(!dir.path().join("resourcepacks/pack/b.png").exists());
+        assert_eq!(ArchiveRecord::list(&root).len(), 2);
+
+        //a file of the manifest overriding an entry doesn't make the archive look broken
+        files.files = vec![File {
+            url: server.url("/a.png"),
+            size: 4,
+            hash: hex::encode(Sha1::digest(b"mine")),
+            path: String::from("resourcepacks/pack/a.png"),
+            ..Default::default()
+        }];
+        files.sync(&root, |_| false).await.ok().unwrap();
+        files.sync(&root, |_| false).await.ok().unwrap();
+        assert_eq!(std::fs::read(dir.path().join("resourcepacks/pack/a.png")).unwrap(), b"mine");
+        assert_eq!(server.requests("/v2.zip").len(), 1);
+
+        files.files.clear();
+        files.archives = vec![archive(&server, "/evil.zip", &evil, ArchiveFormat::Zip, "resourcepacks")];
+        assert!(matches!(files.sync(&root, |_| false).await, Err(DownloadError::Archive(_))));
+        assert!(!dir.path().join("options.txt").exists());
+        assert!(!dir.path().join("resourcepacks/ok.png").exists());
+    }
+
+    #[tokio::test]
+    async fn prefetches_into_staging_only() {
+        let server = crate::test_server::TestServer::start(vec![("/x.jar", b"content".to_vec())]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = cache::Store::new(dir.path().join("cache"));
+        let files: Files = vec![File {
+            url: server.url("/x.jar"),
+            size: 7,
+            hash: hex::encode(Sha1::digest(b"content")),
+            path: String::from("../../escaped.jar"),
+            ..Default::default()
+        }].into();
+
+        assert_eq!(files.prefetch_into(&store).await.ok().unwrap(), 1);
+        assert!(!dir.path().join("escaped.jar").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detached_files_no_longer_change_the_cached_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let (object, file) = (dir.path().join("object"), dir.path().join("options.txt"));
+        std::fs::write(&object, "fov:70").unwrap();
+        std::fs::hard_link(&object, &file).unwrap();
+
+        detach(&file).ok().unwrap();
+        std::fs::write(&file, "fov:90").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&object).unwrap(), "fov:70");
+        assert!(!is_shared(&object));
+
+        use std::os::unix::fs::PermissionsExt;
+        let script = dir.path().join("run.sh");
+        std::fs::set_permissions(&object, std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::hard_link(&object, &script).unwrap();
+        set_executable(&script).ok().unwrap();
+        assert_eq!(std::fs::metadata(&object).unwrap().permissions().mode() & 0o777, 0o644);
+        assert_eq!(std::fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o755);
+    }
+
+    #[tokio::test]
+    async fn syncs_a_versioned_manifest() {
+        let server = crate::test_server::TestServer::start(vec![
+            ("/run.sh", b"#!/bin/sh".to_vec()),
+            ("/options.txt", b"fov:70".to_vec()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy();
+        std::fs::write(dir.path().join("options.txt"), "fov:90").unwrap();
+        let other_os = if Platform::current().name == "windows" { "linux" } else { "windows" };
+        let manifest = serde_json::json!({
+            "version": 2,
+            "changelog": "new launcher script",
+            "files": [
+                {"url": server.url("/run.sh"), "size": 9, "algorithm": "sha256", "executable": true,
+                 "hash": hex::encode(sha2::Sha256::digest(b"#!/bin/sh")), "path": "bin/run.sh"},
+                {"url": server.url("/options.txt"), "size": 6, "hash": hex::encode(Sha1::digest(b"fov:70")),
+                 "path": "options.txt", "only_if_missing": true},
+                {"url": server.url("/missing"), "size": 1, "hash": "", "path": "lib/other.dll", "os": [other_os]},
+            ],
+            "directories": [{"path": "saves"}],
+            "links": [{"path": "bin/latest.sh", "target": "run.sh"}, {"path": "escape", "target": "../x", "os": [other_os]}],
+        });
+        let files: Files = serde_json::from_value(manifest).unwrap();
+
+        files.sync(&root, |_| false).await.ok().unwrap();
+
+        assert_eq!(std::fs::read_to_string(dir.path().join("options.txt")).unwrap(), "fov:90");
+        assert!(!dir.path().join("lib/other.dll").exists());
+        assert!(dir.path().join("saves").is_dir());
+        assert_eq!(std::fs::read(dir.path().join("bin/latest.sh")).unwrap(), b"#!/bin/sh");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_ne!(std::fs::metadata(dir.path().join("bin/run.sh")).unwrap().permissions().mode() & 0o111, 0);
+        }
+        files.remove_old_files(dir.path());
+        assert!(dir.path().join("options.txt").exists());
+        let escaping = Link { path: String::from("mods/x"), target: String::from("../../etc/passwd"), ..Default::default() };
+        assert!(escaping.create(&root).is_err());
+        let json = serde_json::to_value(&files).unwrap();
+        assert_eq!((json["version"].as_u64(), json["files"][0]["algorithm"].as_str()), (Some(2), Some("sha256")));
+    }
+
+    #[tokio::test]
+    async fn lists_every_missing_file_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy();
+        std::fs::create_dir_all(dir.path().join("mods")).unwrap();
+        std::fs::write(dir.path().join("mods/present.jar"), "present").unwrap();
+        std::fs::write(dir.path().join("mods/corrupted.jar"), "corrupted").unwrap();
+        let file = |path: &str, content: &[u8]| File {
+            url: format!("http://127.0.0.1:9/{}", path),
+            size: content.len() as u64,
+            hash: hex::encode(Sha1::digest(content)),
+            path: path.to_string(),
+            ..Default::default()
+        };
+        let mut files: Files = vec![
+            file("mods/present.jar", b"present"),
+            file("mods/corrupted.jar", b"intact"),
+            file("mods/absent.jar", b"absent"),
+            file("config/absent.toml", b"absent"),
+        ].into();
+        files.archives.push(Archive {
+            url: String::from("http://127.0.0.1:9/shaders.zip"),
+            size: 1,
+            hash: hex::encode(Sha1::digest(b"shaders")),
+            target: String::from("shaderpacks"),
+            ..Default::default()
+        });
+
+        network::set_offline(true);
+        let result = files.sync(&root, |_| false).await;
+        network::set_offline(false);
+
+        let Err(DownloadError::Missing(missing)) = result else {
+            panic!("the missing files aren't reported");
+        };
+        let path = |relative: &str| dir.path().join(relative).display().to_string();
+        assert_eq!(missing, [
+            path("config/absent.toml"),
+            path("mods/absent.jar"),
+            path("mods/corrupted.jar"),
+            String::from("http://127.0.0.1:9/shaders.zip"),
+        ]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("mods/corrupted.jar")).unwrap(), "corrupted");
+    }
+}
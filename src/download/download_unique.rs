@@ -1,28 +1,63 @@
+use super::DownloadError;
+use std::{path::{Path, PathBuf}, io::{Seek, SeekFrom, Write}, ops::Range};
+use std::sync::atomic::{AtomicU64, Ordering};
+use reqwest::header::{ACCEPT_RANGES, RANGE};
+use tokio::sync::OwnedSemaphorePermit;
+use crate::{cache, env, log, network};
 
-use super::{file_to_hash, DownloadError};
-use std::{path::{Path, PathBuf}, io::Write};
-use md5::Md5;
-use futures_util::StreamExt;
+//smaller files aren't worth the extra requests
+const SEGMENTED_MIN_SIZE: u64 = 4 << 20;
+const SEGMENT_ATTEMPTS: usize = 3;
 
-async fn download_header(url: &str) -> Result<reqwest::header::HeaderMap, DownloadError> {
-    let client = reqwest::Client::new();
-    let response = client.head(url).send().await.map_err(DownloadError::Reqwest)?;
-    Ok(response.headers().clone())
+//percent of the file reported so far, shared by the segments. Never goes back when a transfer restarts
+struct Progress<F> {
+    total: u64,
+    bytes: AtomicU64,
+    percent: AtomicU64,
+    callback_send_delta: F,
 }
 
-pub async fn download_file<P, F>(url: &str, path: P, callback_send_delta: F) -> Result<(), DownloadError> 
-where
-    P: AsRef<Path>,
-    F: Fn(u32) -> bool
-{
-    let path = path.as_ref();
-    let response = reqwest::get(url).await
-        .map_err(DownloadError::Reqwest)?;
-    let (content_length, mut stream) = (response.content_length(), response.bytes_stream());
-    
+impl<F: Fn(u32) -> bool> Progress<F> {
+    fn new(total: u64, callback_send_delta: F) -> Self {
+        Self { total, bytes: AtomicU64::new(0), percent: AtomicU64::new(0), callback_send_delta }
+    }
+
+    fn restart(&self, total: u64) -> Self
+    where
+        F: Clone
+    {
+        Self {
+            total,
+            bytes: AtomicU64::new(0),
+            percent: AtomicU64::new(self.percent.load(Ordering::Relaxed)),
+            callback_send_delta: self.callback_send_delta.clone(),
+        }
+    }
+
+    fn advance(&self, bytes: u64) -> Result<(), DownloadError> {
+        if self.total == 0 {
+            return Ok(());
+        }
+        let current_bytes = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let next_percent = (current_bytes * 100 / self.total).min(100);
+        let current_percent = self.percent.fetch_max(next_percent, Ordering::Relaxed);
+        if next_percent > current_percent && (self.callback_send_delta)((next_percent - current_percent) as u32) {
+            return Err(DownloadError::DownloadStopped);
+        }
+        Ok(())
+    }
+}
+
+//the size of the file when the server serves it in byte ranges
+fn ranged_length(response: &reqwest::Response) -> Option<u64> {
+    let ranges = response.headers().get(ACCEPT_RANGES).and_then(|v| v.to_str().ok()) == Some("bytes");
+    ranges.then_some(response.content_length()?)
+}
+
+fn create_parent(path: &Path) -> Result<(), DownloadError> {
     let parent = path.parent()
         .map(|p| p.to_path_buf())
-        .unwrap_or_else(|| 
+        .unwrap_or_else(||
             std::env::current_dir()
             .ok()
             .unwrap_or_else(|| PathBuf::from("."))
@@ -31,26 +66,232 @@ where
         std::fs::create_dir_all(parent)
             .map_err(DownloadError::Io)?;
     }
+    Ok(())
+}
+
+//large files are fetched in env::DOWNLOAD_SEGMENTS ranges at once when the server allows it,
+//in a single stream otherwise
+pub async fn download_file<P, F>(url: &str, path: P, callback_send_delta: F) -> Result<(), DownloadError>
+where
+    P: AsRef<Path>,
+    F: Fn(u32) -> bool
+{
+    download(url, path.as_ref(), None, &callback_send_delta).await
+}
 
-    let mut file = std::fs::File::create(path)
+//same, for a file whose size is known: small ones are streamed at once, large ones are asked in ranges
+//straight away
+pub async fn download_sized<P, F>(url: &str, path: P, size: u64, callback_send_delta: F) -> Result<(), DownloadError>
+where
+    P: AsRef<Path>,
+    F: Fn(u32) -> bool
+{
+    download(url, path.as_ref(), Some(size), &callback_send_delta).await
+}
+
+async fn download<F>(url: &str, path: &Path, size: Option<u64>, callback_send_delta: F) -> Result<(), DownloadError>
+where
+    F: Fn(u32) -> bool + Clone
+{
+    create_parent(path)?;
+    let progress = Progress::new(0, callback_send_delta);
+    if let Some(length) = size.filter(|l| *l >= SEGMENTED_MIN_SIZE) {
+        return download_large(url, path, length, &progress).await;
+    }
+    //an unknown size is learnt from the answer to the whole file, dropped when it is worth segmenting
+    let response = open(url).await?;
+    if let Some(length) = ranged_length(&response.1).filter(|l| *l >= SEGMENTED_MIN_SIZE) {
+        drop(response);
+        return download_large(url, path, length, &progress).await;
+    }
+    download_stream(response, path, &progress).await
+}
+
+//segmented, in one stream when the server turns out not to handle ranges
+async fn download_large<F>(url: &str, path: &Path, length: u64, progress: &Progress<F>) -> Result<(), DownloadError>
+where
+    F: Fn(u32) -> bool + Clone
+{
+    let segmented = progress.restart(length);
+    match download_segmented(url, path, length, &segmented).await {
+        Err(e) if e.is_remote() => log!("WARN", url, "segmented download failed, retrying in one stream: {}", e),
+        result => return result,
+    }
+    download_stream(open(url).await?, path, &segmented).await
+}
+
+//the answer to a GET of the whole file, with the connection it holds
+async fn open(url: &str) -> Result<(Option<OwnedSemaphorePermit>, reqwest::Response), DownloadError> {
+    let connection = network::connection(url).await;
+    let response = network::get(url).await
+        .and_then(|r| r.error_for_status())
+        .map_err(DownloadError::Reqwest)?;
+    Ok((connection, response))
+}
+
+async fn download_stream<F>((_connection, response): (Option<OwnedSemaphorePermit>, reqwest::Response), path: &Path, progress: &Progress<F>) -> Result<(), DownloadError>
+where
+    F: Fn(u32) -> bool + Clone
+{
+    let (content_length, mut stream) = (response.content_length(), response.bytes_stream());
+    let progress = progress.restart(content_length.unwrap_or(0));
+
+    let mut file = cache::create_file(path)
         .map_err(DownloadError::Io)?;
-    let mut current_bytes = 0;
-    let mut current_percent = 0;
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(DownloadError::Reqwest)?;
-        if let Some(length) = content_length {
-            current_bytes += chunk.len();
-            let next_percent = current_bytes * 100 / length as usize;
-            if next_percent > current_percent {
-                if callback_send_delta((next_percent - current_percent) as u32) {
-                    return Err(DownloadError::DownloadStopped);
-                }
-                current_percent = next_percent;
-            }
-        }
+    while let Some(chunk) = network::next_chunk(&mut stream).await {
+        let chunk = chunk?;
         file
             .write_all(&chunk)
             .map_err(DownloadError::Io)?;
+        progress.advance(chunk.len() as u64)?;
+    }
+    Ok(())
+}
+
+async fn download_segmented<F>(url: &str, path: &Path, length: u64, progress: &Progress<F>) -> Result<(), DownloadError>
+where
+    F: Fn(u32) -> bool
+{
+    let file = cache::create_file(path).map_err(DownloadError::Io)?;
+    file.set_len(length).map_err(DownloadError::Io)?;
+    let segments = env::DOWNLOAD_SEGMENTS as u64;
+    let segment_size = length.div_ceil(segments);
+    let ranges = (0..segments)
+        .map(|i| i * segment_size..((i + 1) * segment_size).min(length))
+        .filter(|r| !r.is_empty());
+    futures_util::future::try_join_all(ranges.map(|range| download_segment(url, path, range, progress))).await?;
+    Ok(())
+}
+
+//a failed segment is resumed where it stopped, the other ones go on
+async fn download_segment<F>(url: &str, path: &Path, range: Range<u64>, progress: &Progress<F>) -> Result<(), DownloadError>
+where
+    F: Fn(u32) -> bool
+{
+    let mut position = range.start;
+    let mut attempt = 1;
+    loop {
+        match fetch_range(url, path, &mut position, range.end, progress).await {
+            Err(e) if e.is_remote() && attempt < SEGMENT_ATTEMPTS => {
+                log!("WARN", url, "bytes {}-{} failed, retrying: {}", position, range.end - 1, e);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn fetch_range<F>(url: &str, path: &Path, position: &mut u64, end: u64, progress: &Progress<F>) -> Result<(), DownloadError>
+where
+    F: Fn(u32) -> bool
+{
+    let _connection = network::connection(url).await;
+    let response = network::client().get(url)
+        .header(RANGE, format!("bytes={}-{}", position, end - 1))
+        .send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(DownloadError::Reqwest)?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(DownloadError::Range(format!("{} ignored the requested range", url)));
+    }
+    let mut file = std::fs::OpenOptions::new().write(true).open(path).map_err(DownloadError::Io)?;
+    file.seek(SeekFrom::Start(*position)).map_err(DownloadError::Io)?;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = network::next_chunk(&mut stream).await {
+        let chunk = chunk?;
+        //a server sending more than asked must not overwrite the next segment
+        let chunk = &chunk[..chunk.len().min((end - *position) as usize)];
+        file.write_all(chunk).map_err(DownloadError::Io)?;
+        *position += chunk.len() as u64;
+        progress.advance(chunk.len() as u64)?;
+    }
+    if *position < end {
+        return Err(DownloadError::Range(format!("{} stopped at byte {} of {}", url, position, end)));
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+//the first of the given urls (and their mirrors) that answers, for files whose hash isn't known
+pub async fn download_first<P, F>(urls: &[String], path: P, callback_send_delta: F) -> Result<(), DownloadError>
+where
+    P: AsRef<Path>,
+    F: Fn(u32) -> bool
+{
+    let mut last_error = DownloadError::NotFound(path.as_ref().display().to_string());
+    for url in network::candidates(urls) {
+        match download_file(&url, path.as_ref(), &callback_send_delta).await {
+            Err(e) if e.is_remote() => {
+                log!("WARN", url, "{}", e);
+                network::mark_unhealthy(&url);
+                last_error = e;
+            }
+            result => return result,
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    fn large_content() -> Vec<u8> {
+        (0..SEGMENTED_MIN_SIZE + 12345).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn downloads_large_files_in_segments() {
+        let content = large_content();
+        let server = crate::test_server::TestServer::start(vec![("/runtime.zip", content.clone())]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runtime.zip");
+        let reported = AtomicU32::new(0);
+
+        download_file(&server.url("/runtime.zip"), &path, |delta| {
+            reported.fetch_add(delta, Ordering::Relaxed);
+            false
+        }).await.ok().unwrap();
+
+        assert!(std::fs::read(&path).unwrap() == content);
+        assert_eq!(reported.load(Ordering::Relaxed), 100);
+        //the whole file first, given up for the ranges
+        assert_eq!(server.requests("/runtime.zip").len(), 1 + env::DOWNLOAD_SEGMENTS);
+    }
+
+    #[tokio::test]
+    async fn resumes_a_failed_segment() {
+        let content = large_content();
+        let server = crate::test_server::TestServer::start(vec![("/lib/modules", content.clone())]).await;
+        server.fail_once("/lib/modules");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modules");
+
+        download_sized(&server.url("/lib/modules"), &path, content.len() as u64, |_| false).await.ok().unwrap();
+
+        assert!(std::fs::read(&path).unwrap() == content);
+        assert_eq!(server.requests("/lib/modules").len(), env::DOWNLOAD_SEGMENTS + 1);
+    }
+
+    #[tokio::test]
+    async fn streams_when_ranges_are_not_supported() {
+        let content = large_content();
+        let server = crate::test_server::TestServer::start(vec![
+            ("/launcher.exe", content.clone()),
+            ("/options.txt", b"fov:70".to_vec()),
+        ]).await;
+        server.without_ranges("/launcher.exe");
+        let dir = tempfile::tempdir().unwrap();
+
+        download_file(&server.url("/launcher.exe"), dir.path().join("a.exe"), |_| false).await.ok().unwrap();
+        download_sized(&server.url("/launcher.exe"), dir.path().join("b.exe"), content.len() as u64, |_| false).await.ok().unwrap();
+        download_file(&server.url("/options.txt"), dir.path().join("options.txt"), |_| false).await.ok().unwrap();
+
+        assert!(std::fs::read(dir.path().join("a.exe")).unwrap() == content);
+        assert!(std::fs::read(dir.path().join("b.exe")).unwrap() == content);
+        assert_eq!(std::fs::read(dir.path().join("options.txt")).unwrap(), b"fov:70");
+        //small files are a single GET, never preceded by a HEAD
+        assert_eq!(server.requests("/options.txt").len(), 1);
+        assert!(server.requests("").iter().all(|r| r.starts_with("GET ")));
+    }
+}
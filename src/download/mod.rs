@@ -1,14 +1,33 @@
 mod download_unique;
+mod download_list;
+mod archive;
 pub use download_unique::*;
+pub use download_list::*;
+pub use archive::*;
 
+use serde::{Deserialize, Serialize};
 use sha1::Digest;
+use std::io::Write;
 use std::path::Path;
+use std::time::Duration;
 use core::fmt;
+use crate::signature::SignatureError;
 
 pub enum DownloadError {
     Reqwest(reqwest::Error),
     Io(std::io::Error),
     HeaderEncoding(reqwest::header::ToStrError),
+    HashMismatch(String),
+    NotFound(String),
+    //offline mode: files that would have to be downloaded
+    Missing(Vec<String>),
+    //nothing received for the configured read timeout
+    Timeout(Duration),
+    //a segment of a ranged download not served as asked
+    Range(String),
+    Signature(SignatureError),
+    //an archive that can't be read or extracted
+    Archive(String),
     DownloadStopped,
 }
 
@@ -18,12 +37,35 @@ impl fmt::Display for DownloadError {
             DownloadError::Reqwest(e) => write!(f, "Reqwest error: {}", e),
             DownloadError::Io(e) => write!(f, "IO error: {}", e),
             DownloadError::HeaderEncoding(e) => write!(f, "Header encoding error: {}", e),
+            DownloadError::HashMismatch(path) => write!(f, "Hash mismatch for {}", path),
+            DownloadError::NotFound(name) => write!(f, "{} not found in any repository", name),
+            DownloadError::Missing(paths) => write!(f, "Offline, {} files missing:\n  {}", paths.len(), paths.join("\n  ")),
+            DownloadError::Timeout(after) => write!(f, "No data received for {}s", after.as_secs()),
+            DownloadError::Range(reason) => write!(f, "Range request failed: {}", reason),
+            DownloadError::Signature(e) => write!(f, "Manifest refused: {}", e),
+            DownloadError::Archive(reason) => write!(f, "Archive error: {}", reason),
             DownloadError::DownloadStopped => write!(f, "Download stopped"),
         }
     }
 }
 
-fn file_to_hash<H, P>(path: P) -> Result<Vec<u8>, DownloadError> 
+impl DownloadError {
+    //failures of the server rather than of this machine, worth retrying on another url
+    pub fn is_remote(&self) -> bool {
+        matches!(self, DownloadError::Reqwest(_) | DownloadError::Timeout(_) | DownloadError::HashMismatch(_) | DownloadError::Range(_))
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, DownloadError::Reqwest(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND))
+    }
+
+    //offline report of a single file
+    pub fn missing<P: AsRef<Path>>(path: P) -> DownloadError {
+        DownloadError::Missing(vec![path.as_ref().display().to_string()])
+    }
+}
+
+pub fn file_to_hash<H, P>(path: P) -> Result<Vec<u8>, DownloadError> 
 where
     H: Digest + std::io::Write,
     P: AsRef<Path>,
@@ -32,4 +74,124 @@ where
     let mut hasher = H::new();
     std::io::copy(&mut file, &mut hasher).map_err(DownloadError::Io)?;
     Ok(hasher.finalize().to_vec())
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+    Md5,
+}
+
+impl HashAlgorithm {
+    //the names used in the manifest
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            "md5" => Some(HashAlgorithm::Md5),
+            _ => None,
+        }
+    }
+
+    pub fn is_sha1(&self) -> bool {
+        *self == HashAlgorithm::Sha1
+    }
+
+    //hex digest of the file at path
+    pub fn file_hash<P: AsRef<Path>>(self, path: P) -> Result<String, DownloadError> {
+        let hash = match self {
+            HashAlgorithm::Sha1 => file_to_hash::<sha1::Sha1, _>(path)?,
+            HashAlgorithm::Sha256 => file_to_hash::<sha2::Sha256, _>(path)?,
+            HashAlgorithm::Sha512 => file_to_hash::<sha2::Sha512, _>(path)?,
+            HashAlgorithm::Md5 => file_to_hash::<md5::Md5, _>(path)?,
+        };
+        Ok(hex::encode(hash))
+    }
+}
+
+//how a payload is compressed on the server, the file is written decompressed
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Gzip,
+    Xz,
+}
+
+//refuses to write more than the expected size, a payload inflating past it isn't the announced file
+pub struct Capped<W: Write> {
+    inner: W,
+    remaining: Option<u64>,
+}
+
+impl<W: Write> Capped<W> {
+    //no limit when the size is unknown (0)
+    pub fn new(inner: W, size: u64) -> Self {
+        Self { inner, remaining: (size > 0).then_some(size) }
+    }
+}
+
+impl<W: Write> Write for Capped<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        if let Some(remaining) = &mut self.remaining {
+            if bytes.len() as u64 > *remaining {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "larger than announced"));
+            }
+            *remaining -= bytes.len() as u64;
+        }
+        self.inner.write_all(bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+//decompresses what is written to it into the file, as it arrives
+pub enum Decompressor<W: Write> {
+    Plain(W),
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+    Gzip(flate2::write::GzDecoder<W>),
+    Xz(xz2::write::XzDecoder<W>),
+}
+
+impl<W: Write> Decompressor<W> {
+    pub fn new(compression: Option<Compression>, writer: W) -> std::io::Result<Self> {
+        Ok(match compression {
+            None => Decompressor::Plain(writer),
+            Some(Compression::Zstd) => Decompressor::Zstd(zstd::stream::write::Decoder::new(writer)?),
+            Some(Compression::Gzip) => Decompressor::Gzip(flate2::write::GzDecoder::new(writer)),
+            Some(Compression::Xz) => Decompressor::Xz(xz2::write::XzDecoder::new(writer)),
+        })
+    }
+
+    pub fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            Decompressor::Plain(w) => w.write_all(bytes),
+            Decompressor::Zstd(w) => w.write_all(bytes),
+            Decompressor::Gzip(w) => w.write_all(bytes),
+            Decompressor::Xz(w) => w.write_all(bytes),
+        }
+    }
+
+    //writes out what the decoders still hold. A truncated payload is caught by the hash check
+    pub fn finish(self) -> std::io::Result<W> {
+        let mut writer = match self {
+            Decompressor::Plain(w) => w,
+            Decompressor::Zstd(mut w) => {
+                w.flush()?;
+                w.into_inner()
+            }
+            Decompressor::Gzip(w) => w.finish()?,
+            Decompressor::Xz(mut w) => w.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
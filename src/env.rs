@@ -1,25 +1,53 @@
 use std::time::Duration;
 
 mod detail_env {
+    use crate::config::Config;
+    use crate::instance::Instance;
     use crate::launcher::Launcher;
     pub const URL: &str = "https://launcher.mojang.com/download/Minecraft.exe";
     pub const PATH: &str = "Minecraft/Minecraft Launcher/MinecraftLauncher.exe";
+    pub const CONFIG_PATH: &str = "launcher.json";
+    //the official launcher's "../.minecraft", shared with direct launches
+    pub const WORK_DIR: &str = "Minecraft/.minecraft";
+    pub const RUNTIME_DIR: &str = "Minecraft/runtime";
+    pub const INSTANCES_DIR: &str = "Minecraft/instances";
+    pub const JAVA_CACHE_PATH: &str = "Minecraft/java.json";
+    //content-addressed store shared by every instance, see cache::Store
+    pub const CACHE_DIR: &str = "Minecraft/cache";
+    //written by `bandwidth`, read by the running launchers
+    pub const BANDWIDTH_PATH: &str = "Minecraft/bandwidth";
+    pub const RESOURCES_URL: &str = "https://resources.download.minecraft.net";
 
     lazy_static::lazy_static! {
-        pub static ref LAUNCHER: Option<Launcher> = Some(Launcher {
+        pub static ref CONFIG: Config = Config::load(CONFIG_PATH);
+    }
+
+    //the official launcher, started in the game directory of the selected instance. Its own directories are
+    //given from the launcher's folder, where it resolves them, so they don't depend on where it runs
+    pub fn launcher(instance: &Instance) -> Launcher {
+        let root = std::env::current_dir().unwrap_or_default();
+        let launcher_dir = root.join(PATH).parent().expect("launcher has a parent").to_path_buf();
+        let lossy = |path: std::path::PathBuf| path.to_string_lossy().into_owned();
+        Launcher {
             program: String::from(PATH),
             arguments: vec![
                 "--workDir".into(),
-                "../.minecraft".into(),
+                lossy(root.join(WORK_DIR)),
                 "--tmpDir".into(),
-                "./tmp".into(),
+                lossy(launcher_dir.join("tmp")),
                 "--user-data-dir".into(),
-                "../data user".into()
+                lossy(launcher_dir.join("../data user")),
             ],
-        });
+            working_dir: Some(root.join(&instance.game_dir)),
+        }
     }
 }
 
+//base64 ed25519 public keys trusted for instance manifests, on top of launcher.json's manifest_keys
+pub const MANIFEST_KEYS: &[&str] = &[];
 pub const DELAY_WINDOW: Duration = Duration::from_millis(500);
+pub const DOWNLOAD_CONCURRENCY: usize = 8;
+//ranges of a single large file fetched at once
+pub const DOWNLOAD_SEGMENTS: usize = 4;
 
 pub use detail_env::*;
\ No newline at end of file
@@ -0,0 +1,396 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use core::fmt;
+use crate::download::{DownloadError, Files};
+use crate::{env, log, network, signature};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoaderKind {
+    Fabric,
+    Quilt,
+    Forge,
+    NeoForge,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Loader {
+    pub kind: LoaderKind,
+    pub version: String,
+}
+
+impl Loader {
+    //id of the version json the loader's own installer would write for this game version
+    pub fn version_id(&self, game_version: &str) -> String {
+        match self.kind {
+            LoaderKind::Fabric => format!("fabric-loader-{}-{}", self.version, game_version),
+            LoaderKind::Quilt => format!("quilt-loader-{}-{}", self.version, game_version),
+            LoaderKind::Forge => format!("{}-forge-{}", game_version, self.version),
+            LoaderKind::NeoForge => format!("neoforge-{}", self.version),
+        }
+    }
+}
+
+//instances/<name>/instance.json, the game itself lives in instances/<name>/minecraft
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct InstanceConfig {
+    //falls back to the "version" of launcher.json
+    pub version: Option<String>,
+    pub jvm_args: Vec<String>,
+    //list of files (download_list::Files) kept in sync in the game directory
+    pub manifest_url: Option<String>,
+    //icon of the official launcher's profile, "Grass" when unset
+    pub icon: Option<String>,
+    //mod loader required by the instance, recorded by modpack installs
+    pub loader: Option<Loader>,
+}
+
+pub struct Instance {
+    pub name: String,
+    pub game_dir: PathBuf,
+    pub config: InstanceConfig,
+}
+
+pub enum InstanceError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    NotFound(String),
+    AlreadyExists(String),
+    InvalidName(String),
+}
+
+impl fmt::Display for InstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceError::Io(e) => write!(f, "IO error: {}", e),
+            InstanceError::Json(e) => write!(f, "JSON error: {}", e),
+            InstanceError::NotFound(name) => write!(f, "Instance {} not found", name),
+            InstanceError::AlreadyExists(name) => write!(f, "Instance {} already exists", name),
+            InstanceError::InvalidName(name) => write!(f, "Invalid instance name {}", name),
+        }
+    }
+}
+
+//name of the implicit instance used while none was created, it plays in the shared work directory
+pub const DEFAULT_INSTANCE: &str = "default";
+
+#[cfg(not(test))]
+fn instances_dir() -> PathBuf {
+    PathBuf::from(env::INSTANCES_DIR)
+}
+
+//tests run side by side, each in an instances directory of its own
+#[cfg(test)]
+thread_local! {
+    static INSTANCES_DIR: std::cell::RefCell<PathBuf> = std::cell::RefCell::new(PathBuf::from(env::INSTANCES_DIR));
+}
+
+#[cfg(test)]
+fn instances_dir() -> PathBuf {
+    INSTANCES_DIR.with(|dir| dir.borrow().clone())
+}
+
+fn instance_dir(name: &str) -> PathBuf {
+    instances_dir().join(name)
+}
+
+//what a clone takes from its source: the player's data, never the versions, libraries and assets of the shared
+//work directory
+const GAME_DATA: &[&str] = &["saves", "config", "mods", "options.txt"];
+
+fn check_name(name: &str) -> Result<(), InstanceError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid { Ok(()) } else { Err(InstanceError::InvalidName(name.to_string())) }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+impl Instance {
+    pub fn implicit() -> Instance {
+        Instance {
+            name: String::from(DEFAULT_INSTANCE),
+            game_dir: PathBuf::from(env::WORK_DIR),
+            config: InstanceConfig::default(),
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Instance, InstanceError> {
+        check_name(name)?;
+        let dir = instance_dir(name);
+        let bytes = std::fs::read(dir.join("instance.json")).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => InstanceError::NotFound(name.to_string()),
+            _ => InstanceError::Io(e),
+        })?;
+        Ok(Instance {
+            name: name.to_string(),
+            game_dir: dir.join("minecraft"),
+            config: serde_json::from_slice(&bytes).map_err(InstanceError::Json)?,
+        })
+    }
+
+    pub fn list() -> Result<Vec<Instance>, InstanceError> {
+        let Ok(entries) = std::fs::read_dir(instances_dir()) else {
+            return Ok(Vec::new());
+        };
+        let mut instances = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match Instance::load(&name) {
+                Ok(instance) => instances.push(instance),
+                Err(e) => log!("WARN", name, "ignored: {}", e),
+            }
+        }
+        instances.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(instances)
+    }
+
+    //the instance given on the command line, else the one of launcher.json, else the implicit one
+    pub fn select(name: Option<&str>) -> Result<Instance, InstanceError> {
+        match name.or(env::CONFIG.instance.as_deref()) {
+            Some(DEFAULT_INSTANCE) | None => Ok(Instance::implicit()),
+            Some(name) => Instance::load(name),
+        }
+    }
+
+    pub fn create(name: &str, config: InstanceConfig) -> Result<Instance, InstanceError> {
+        check_name(name)?;
+        let dir = instance_dir(name);
+        if name == DEFAULT_INSTANCE || dir.exists() {
+            return Err(InstanceError::AlreadyExists(name.to_string()));
+        }
+        let instance = Instance {
+            name: name.to_string(),
+            game_dir: dir.join("minecraft"),
+            config,
+        };
+        std::fs::create_dir_all(&instance.game_dir).map_err(InstanceError::Io)?;
+        instance.save()?;
+        Ok(instance)
+    }
+
+    pub fn save(&self) -> Result<(), InstanceError> {
+        let bytes = serde_json::to_vec_pretty(&self.config).map_err(InstanceError::Json)?;
+        std::fs::write(instance_dir(&self.name).join("instance.json"), bytes).map_err(InstanceError::Io)
+    }
+
+    //the implicit instance is the shared work directory, never removed
+    pub fn delete(name: &str) -> Result<(), InstanceError> {
+        if name == DEFAULT_INSTANCE {
+            return Err(InstanceError::InvalidName(name.to_string()));
+        }
+        let instance = Instance::load(name)?;
+        std::fs::remove_dir_all(instance_dir(&instance.name)).map_err(InstanceError::Io)
+    }
+
+    //copies the worlds, settings and mods of the instance into a new one
+    pub fn clone_to(&self, name: &str) -> Result<Instance, InstanceError> {
+        let target = Instance::create(name, self.config.clone())?;
+        let copied = GAME_DATA.iter().try_for_each(|entry| {
+            let from = self.game_dir.join(entry);
+            match std::fs::metadata(&from) {
+                Ok(metadata) if metadata.is_dir() => copy_dir(&from, &target.game_dir.join(entry)),
+                Ok(_) => std::fs::copy(&from, target.game_dir.join(entry)).map(|_| ()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e),
+            }
+        });
+        if let Err(e) = copied {
+            let _ = std::fs::remove_dir_all(instance_dir(name));
+            return Err(InstanceError::Io(e));
+        }
+        Ok(target)
+    }
+
+    pub fn version(&self) -> &str {
+        self.config.version.as_deref().unwrap_or(&env::CONFIG.version)
+    }
+
+    //version json to launch: the loader's when there is one, else the game's
+    pub fn version_id(&self) -> String {
+        match &self.config.loader {
+            Some(loader) => loader.version_id(self.version()),
+            None => self.version().to_string(),
+        }
+    }
+
+    //download the files of the instance manifest into the game directory
+    pub async fn sync_files<F>(&self, callback_send_delta: F) -> Result<(), DownloadError>
+    where
+        F: Fn(u32) -> bool
+    {
+        let Some(url) = &self.config.manifest_url else {
+            return Ok(());
+        };
+        //the last manifest seen is kept for offline launches
+        let cache_path = instance_dir(&self.name).join("files.json");
+        let files: Files = if network::is_offline() {
+            let bytes = std::fs::read(&cache_path).map_err(|_| DownloadError::missing(&cache_path))?;
+            serde_json::from_slice(&bytes).map_err(|e| DownloadError::Io(e.into()))?
+        } else {
+            let previous = self.applied_version();
+            let files = fetch_manifest(url, previous).await?;
+            if let (Some(version), Some(changelog)) = (files.version, &files.changelog) {
+                if previous.is_none_or(|previous| previous < version) {
+                    log!("INFO", self.name, "files updated to version {}: {}", version, changelog);
+                }
+            }
+            let bytes = serde_json::to_vec(&files).map_err(|e| DownloadError::Io(e.into()))?;
+            std::fs::create_dir_all(instance_dir(&self.name)).map_err(DownloadError::Io)?;
+            std::fs::write(&cache_path, bytes).map_err(DownloadError::Io)?;
+            files
+        };
+        files.sync(&self.game_dir.to_string_lossy(), callback_send_delta).await
+    }
+
+    //put the files of the published manifest in the shared cache, for the next sync_files
+    pub async fn prefetch(&self) -> Result<usize, DownloadError> {
+        let Some(url) = &self.config.manifest_url else {
+            return Ok(0);
+        };
+        fetch_manifest(url, self.applied_version()).await?.prefetch().await
+    }
+
+    //version of the last manifest synced, kept in files.json
+    fn applied_version(&self) -> Option<u64> {
+        let bytes = std::fs::read(instance_dir(&self.name).join("files.json")).ok()?;
+        serde_json::from_slice::<Files>(&bytes).ok()?.version
+    }
+}
+
+//the manifest decides what is written and deleted in the game directory: only manifests with a valid detached
+//signature (<url>.sig) by a trusted key are accepted unless allow_unsigned_manifests is set, and never one
+//older than the version already applied
+async fn fetch_manifest(url: &str, applied: Option<u64>) -> Result<Files, DownloadError> {
+    let keys = signature::trusted_keys(&env::CONFIG.manifest_keys).map_err(DownloadError::Signature)?;
+    let resp = network::get_json(url).await.and_then(|r| r.error_for_status()).map_err(DownloadError::Reqwest)?;
+    let bytes = network::body(resp).await?;
+    let mut signature = None;
+    if !keys.is_empty() {
+        let signature_url = signature::signature_url(url);
+        signature = match network::get(&signature_url).await.and_then(|r| r.error_for_status()) {
+            Ok(resp) => Some(String::from_utf8_lossy(&network::body(resp).await?).into_owned()),
+            Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => None,
+            Err(e) => return Err(DownloadError::Reqwest(e)),
+        };
+    }
+    signature::check(url, &bytes, signature.as_deref(), &keys, env::CONFIG.allow_unsigned_manifests).map_err(DownloadError::Signature)?;
+    let files: Files = serde_json::from_slice(&bytes).map_err(|e| DownloadError::Io(e.into()))?;
+    signature::check_version(url, files.version, applied).map_err(DownloadError::Signature)?;
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a fresh instances directory for the calling test
+    fn instances() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        INSTANCES_DIR.with(|instances| *instances.borrow_mut() = dir.path().to_path_buf());
+        dir
+    }
+
+    #[test]
+    fn checks_names() {
+        for name in ["survival", "Pack-1.20.1_fabric"] {
+            assert!(check_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".", "..", "../survival", ".hidden", "a/b", "a\\b", "C:", "créatif", "two words"] {
+            assert!(matches!(check_name(name), Err(InstanceError::InvalidName(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn creates_and_deletes_instances() {
+        let dir = instances();
+        let config = InstanceConfig { version: Some(String::from("1.20.1")), ..Default::default() };
+
+        let created = Instance::create("survival", config).ok().unwrap();
+        assert_eq!(created.game_dir, dir.path().join("survival/minecraft"));
+        assert!(created.game_dir.is_dir());
+        assert_eq!(Instance::load("survival").ok().unwrap().config.version.as_deref(), Some("1.20.1"));
+        assert!(matches!(Instance::create("survival", InstanceConfig::default()), Err(InstanceError::AlreadyExists(_))));
+        assert!(matches!(Instance::create(DEFAULT_INSTANCE, InstanceConfig::default()), Err(InstanceError::AlreadyExists(_))));
+        assert!(matches!(Instance::create("..", InstanceConfig::default()), Err(InstanceError::InvalidName(_))));
+        assert_eq!(Instance::list().ok().unwrap().iter().map(|i| i.name.as_str()).collect::<Vec<_>>(), ["survival"]);
+
+        Instance::delete("survival").ok().unwrap();
+        assert!(!dir.path().join("survival").exists());
+        assert!(matches!(Instance::delete("survival"), Err(InstanceError::NotFound(_))));
+    }
+
+    #[test]
+    fn refuses_to_delete_outside_of_an_instance() {
+        let dir = instances();
+        //a directory named like the implicit instance, and the instances directory itself
+        std::fs::create_dir_all(dir.path().join(DEFAULT_INSTANCE)).unwrap();
+        std::fs::write(dir.path().join(DEFAULT_INSTANCE).join("instance.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("instance.json"), "{}").unwrap();
+
+        assert!(matches!(Instance::delete(DEFAULT_INSTANCE), Err(InstanceError::InvalidName(_))));
+        for name in ["..", ".", "", "../..", "default/.."] {
+            assert!(matches!(Instance::delete(name), Err(InstanceError::InvalidName(_))), "{}", name);
+        }
+        assert!(dir.path().join(DEFAULT_INSTANCE).join("instance.json").exists());
+        assert!(dir.path().join("instance.json").exists());
+    }
+
+    #[test]
+    fn clones_only_the_game_data() {
+        let dir = instances();
+        let source = Instance::create("survival", InstanceConfig { version: Some(String::from("1.20.1")), ..Default::default() }).ok().unwrap();
+        for (path, content) in [
+            ("saves/World/level.dat", "world"),
+            ("config/sodium.json", "{}"),
+            ("options.txt", "fov:90"),
+            ("logs/latest.log", "log"),
+            ("versions/1.20.1/1.20.1.jar", "jar"),
+        ] {
+            std::fs::create_dir_all(source.game_dir.join(path).parent().unwrap()).unwrap();
+            std::fs::write(source.game_dir.join(path), content).unwrap();
+        }
+
+        let clone = source.clone_to("creative").ok().unwrap();
+
+        assert_eq!(clone.config.version.as_deref(), Some("1.20.1"));
+        assert_eq!(std::fs::read_to_string(clone.game_dir.join("saves/World/level.dat")).unwrap(), "world");
+        assert_eq!(std::fs::read_to_string(clone.game_dir.join("config/sodium.json")).unwrap(), "{}");
+        assert_eq!(std::fs::read_to_string(clone.game_dir.join("options.txt")).unwrap(), "fov:90");
+        assert!(!clone.game_dir.join("logs").exists());
+        assert!(!clone.game_dir.join("versions").exists());
+        assert!(!clone.game_dir.join("mods").exists());
+        assert!(source.game_dir.join("saves/World/level.dat").exists());
+        assert!(matches!(source.clone_to("survival"), Err(InstanceError::AlreadyExists(_))));
+        assert!(dir.path().join("survival/minecraft/options.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removes_a_failed_clone() {
+        let dir = instances();
+        let source = Instance::create("survival", InstanceConfig::default()).ok().unwrap();
+        std::fs::create_dir_all(source.game_dir.join("mods")).unwrap();
+        std::fs::write(source.game_dir.join("mods/sodium.jar"), "jar").unwrap();
+        //copying a dangling symlink fails whatever the permissions
+        std::os::unix::fs::symlink("gone.jar", source.game_dir.join("mods/lithium.jar")).unwrap();
+
+        assert!(matches!(source.clone_to("creative"), Err(InstanceError::Io(_))));
+        assert!(!dir.path().join("creative").exists());
+        assert!(source.game_dir.join("mods/sodium.jar").exists());
+    }
+}
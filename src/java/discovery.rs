@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::log;
+use super::java_executable;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JavaInstallation {
+    pub path: PathBuf,
+    pub vendor: String,
+    pub version: String,
+    pub major: u32,
+    pub arch: String,
+    pub portable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    modified: u64,
+    installation: JavaInstallation,
+}
+
+//java executables already inspected, keyed by path and invalidated when the binary changes
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct JavaCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+pub(super) fn executable_name() -> &'static str {
+    if cfg!(target_os = "windows") { "java.exe" } else { "java" }
+}
+
+//every "<dir>/<install>" of a parent directory, the java home being <install>[/<suffix>]
+fn homes_in(parent: &Path, suffix: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path().join(suffix))
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+fn system_homes() -> Vec<PathBuf> {
+    let mut homes = Vec::new();
+    if cfg!(target_os = "windows") {
+        for program_files in ["ProgramFiles", "ProgramFiles(x86)", "ProgramW6432"] {
+            let Some(base) = std::env::var_os(program_files).map(PathBuf::from) else {
+                continue;
+            };
+            for vendor in ["Java", "Eclipse Adoptium", "Eclipse Foundation", "AdoptOpenJDK", "Zulu", "Microsoft", "BellSoft", "Amazon Corretto"] {
+                homes.extend(homes_in(&base.join(vendor), ""));
+            }
+        }
+    } else if cfg!(target_os = "macos") {
+        homes.extend(homes_in(Path::new("/Library/Java/JavaVirtualMachines"), "Contents/Home"));
+    } else {
+        for parent in ["/usr/lib/jvm", "/usr/java", "/opt/java", "/opt/jdk"] {
+            homes.extend(homes_in(Path::new(parent), ""));
+        }
+    }
+    homes
+}
+
+//candidate java executables, portable runtimes first
+pub fn candidates(runtimes_dir: &Path) -> Vec<(PathBuf, bool)> {
+    let mut found = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(runtimes_dir) {
+        found.extend(entries.flatten().map(|entry| (java_executable(&entry.path()), true)));
+    }
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        found.push((PathBuf::from(java_home).join("bin").join(executable_name()), false));
+    }
+    if let Some(path) = std::env::var_os("PATH") {
+        found.extend(std::env::split_paths(&path).map(|dir| (dir.join(executable_name()), false)));
+    }
+    found.extend(system_homes().into_iter().map(|home| (home.join("bin").join(executable_name()), false)));
+
+    let mut seen = HashSet::new();
+    found
+        .into_iter()
+        .filter(|(path, _)| path.is_file())
+        .filter(|(path, _)| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())))
+        .collect()
+}
+
+fn normalize_arch(arch: &str) -> String {
+    match arch.to_lowercase().as_str() {
+        "amd64" | "x86_64" | "x64" => String::from("x86_64"),
+        "aarch64" | "arm64" => String::from("arm64"),
+        "x86" | "i386" | "i486" | "i586" | "i686" => String::from("x86"),
+        other => other.to_string(),
+    }
+}
+
+//"1.8.0_382" is java 8, "17.0.8" is java 17
+pub fn parse_major(version: &str) -> Option<u32> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    match parts.next()?.parse().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
+    }
+}
+
+//the "release" file of the java home: JAVA_VERSION="17.0.8", IMPLEMENTOR="Eclipse Adoptium", OS_ARCH="amd64"
+fn read_release(java: &Path) -> Option<(String, String, String)> {
+    let java = java.canonicalize().ok()?;
+    let home = java.parent()?.parent()?;
+    let content = std::fs::read_to_string(home.join("release")).ok()?;
+    let values = content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim().trim_matches('"')))
+        .collect::<HashMap<_, _>>();
+    Some((
+        values.get("JAVA_VERSION")?.to_string(),
+        values.get("IMPLEMENTOR").unwrap_or(&"unknown").to_string(),
+        values.get("OS_ARCH")?.to_string(),
+    ))
+}
+
+//fallback when there's no release file, "java -version" prints on stderr
+fn run_version(java: &Path) -> Option<(String, String, String)> {
+    let mut command = std::process::Command::new(java);
+    command.arg("-version");
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let output = command.output().ok()?;
+    let text = String::from_utf8_lossy(&output.stderr).into_owned();
+    let mut lines = text.lines();
+    let version = lines.next()?.split('"').nth(1)?.to_string();
+    let vendor = lines.next()
+        .and_then(|line| line.split(" (build").next())
+        .map(|line| line.replace(" Runtime Environment", ""))
+        .unwrap_or_else(|| String::from("unknown"));
+    let arch = if text.contains("64-Bit") { "x86_64" } else { "x86" };
+    Some((version, vendor, arch.to_string()))
+}
+
+pub fn inspect(java: &Path, portable: bool) -> Option<JavaInstallation> {
+    let (version, vendor, arch) = read_release(java).or_else(|| run_version(java))?;
+    Some(JavaInstallation {
+        path: java.to_path_buf(),
+        major: parse_major(&version)?,
+        vendor,
+        version,
+        arch: normalize_arch(&arch),
+        portable,
+    })
+}
+
+fn modified(path: &Path) -> u64 {
+    path.metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl JavaCache {
+    pub fn load<P: AsRef<Path>>(path: P) -> JavaCache {
+        std::fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) {
+        if let Some(parent) = path.as_ref().parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let result = serde_json::to_vec_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|bytes| std::fs::write(&path, bytes));
+        if let Err(e) = result {
+            log!("WARN", path.as_ref().display(), "while saving java cache: {}", e);
+        }
+    }
+
+    //inspect every candidate, reusing the cached result while the executable is unchanged
+    pub fn discover(&mut self, runtimes_dir: &Path) -> Vec<JavaInstallation> {
+        let candidates = candidates(runtimes_dir);
+        self.entries.retain(|path, _| candidates.iter().any(|(candidate, _)| candidate == path));
+        let mut installations = Vec::new();
+        for (path, portable) in candidates {
+            let modified = modified(&path);
+            match self.entries.get(&path) {
+                Some(entry) if entry.modified == modified => installations.push(entry.installation.clone()),
+                _ => {
+                    let Some(installation) = inspect(&path, portable) else {
+                        log!("WARN", path.display(), "not a usable java");
+                        continue;
+                    };
+                    self.entries.insert(path, CacheEntry { modified, installation: installation.clone() });
+                    installations.push(installation);
+                }
+            }
+        }
+        installations
+    }
+}
+
+//exact major version first, a newer one only for modern versions which accept it.
+//on equal terms, the current architecture then the portable runtimes win
+pub fn select_best(installations: &[JavaInstallation], major: u32) -> Option<&JavaInstallation> {
+    let arch = crate::version::Platform::current().arch;
+    installations
+        .iter()
+        .filter(|java| java.major == major || (major >= 17 && java.major > major))
+        .min_by_key(|java| (java.major != major, java.major, java.arch != arch, !java.portable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn java(major: u32, arch: &str, portable: bool) -> JavaInstallation {
+        JavaInstallation {
+            path: PathBuf::from(format!("java-{}-{}-{}", major, arch, portable)),
+            vendor: String::from("Eclipse Adoptium"),
+            version: major.to_string(),
+            major,
+            arch: arch.to_string(),
+            portable,
+        }
+    }
+
+    #[test]
+    fn parses_the_major_version() {
+        for (version, major) in [("1.8.0_382", Some(8)), ("1.7.0", Some(7)), ("17.0.8", Some(17)), ("21", Some(21)),
+            ("21-ea", Some(21)), ("11.0.2+9", Some(11)), ("", None), ("abc", None), ("1", None)] {
+            assert_eq!(parse_major(version), major, "{}", version);
+        }
+    }
+
+    #[test]
+    fn selects_the_best_installation() {
+        let arch = crate::version::Platform::current().arch;
+        let other = if arch == "x86" { "arm64" } else { "x86" };
+        let installations = [java(8, other, false), java(8, arch, false), java(8, arch, true), java(21, arch, false), java(22, arch, true)];
+
+        assert_eq!(select_best(&installations, 8).map(|j| &j.path), Some(&installations[2].path));
+        assert_eq!(select_best(&installations, 17).map(|j| j.major), Some(21));
+        assert_eq!(select_best(&installations, 21).map(|j| &j.path), Some(&installations[3].path));
+        //old versions break on a newer java
+        assert!(select_best(&installations, 16).is_none());
+        assert!(select_best(&[java(8, other, true)], 8).is_some());
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use sysinfo::{System, SystemExt};
+use crate::log;
+use crate::version::{substitute, Variables};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GcPreset {
+    //leave the choice to the jvm
+    None,
+    G1,
+    Aikar,
+    Zgc,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct MemoryConfig {
+    //share of the total physical memory given to the heap
+    pub percent: u64,
+    pub min_mb: u64,
+    pub max_mb: u64,
+    pub gc: GcPreset,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            percent: 50,
+            min_mb: 1024,
+            max_mb: 8192,
+            gc: GcPreset::None,
+        }
+    }
+}
+
+pub struct Heap {
+    pub initial_mb: u64,
+    pub max_mb: u64,
+}
+
+//prepended to the jvm arguments of direct launches and used as the official launcher's javaArgs
+const MEMORY_ARGUMENTS: [&str; 2] = ["-Xms${min_memory}M", "-Xmx${max_memory}M"];
+
+impl Heap {
+    pub fn variables(&self) -> [(&'static str, String); 2] {
+        [("min_memory", self.initial_mb.to_string()), ("max_memory", self.max_mb.to_string())]
+    }
+}
+
+//never take more than what's free right now, machines swapping is worse than a small heap
+pub fn compute_heap(config: &MemoryConfig, total_mb: u64, available_mb: u64) -> Heap {
+    let wanted = (total_mb * config.percent / 100).min(available_mb * 90 / 100);
+    let max_mb = wanted.clamp(config.min_mb, config.max_mb.max(config.min_mb));
+    Heap {
+        initial_mb: config.min_mb.min(max_mb),
+        max_mb,
+    }
+}
+
+pub fn machine_heap(config: &MemoryConfig) -> Heap {
+    let mut system = System::new();
+    system.refresh_memory();
+    let (total_mb, available_mb) = (system.total_memory() / 1024 / 1024, system.available_memory() / 1024 / 1024);
+    let heap = compute_heap(config, total_mb, available_mb);
+    log!("INFO", "Memory: {} MB total, {} MB available, heap {}-{} MB", total_mb, available_mb, heap.initial_mb, heap.max_mb);
+    heap
+}
+
+//flags of a preset for a java major version, zgc falls back to g1 before java 15
+pub fn gc_flags(preset: GcPreset, major: u32) -> Vec<String> {
+    let flags: &[&str] = match preset {
+        GcPreset::None => &[],
+        GcPreset::Zgc if major >= 21 => &["-XX:+UseZGC", "-XX:+ZGenerational"],
+        GcPreset::Zgc if major >= 15 => &["-XX:+UseZGC"],
+        GcPreset::G1 | GcPreset::Zgc => &[
+            "-XX:+UseG1GC", "-XX:+UnlockExperimentalVMOptions", "-XX:G1NewSizePercent=20",
+            "-XX:G1ReservePercent=20", "-XX:MaxGCPauseMillis=50", "-XX:G1HeapRegionSize=32M",
+        ],
+        GcPreset::Aikar => &[
+            "-XX:+UseG1GC", "-XX:+ParallelRefProcEnabled", "-XX:MaxGCPauseMillis=200",
+            "-XX:+UnlockExperimentalVMOptions", "-XX:+DisableExplicitGC", "-XX:+AlwaysPreTouch",
+            "-XX:G1NewSizePercent=30", "-XX:G1MaxNewSizePercent=40", "-XX:G1HeapRegionSize=8M",
+            "-XX:G1ReservePercent=20", "-XX:G1HeapWastePercent=5", "-XX:G1MixedGCCountTarget=4",
+            "-XX:InitiatingHeapOccupancyPercent=15", "-XX:G1MixedGCLiveThresholdPercent=90",
+            "-XX:G1RSetUpdatingPauseIntervalMillis=100", "-XX:SurvivorRatio=32",
+            "-XX:+PerfDisableSharedMem", "-XX:MaxTenuringThreshold=1",
+        ],
+    };
+    flags.iter().map(|flag| flag.to_string()).collect()
+}
+
+//-Xms/-Xmx from the ${min_memory}/${max_memory} variables followed by the gc preset, left out when the
+//java major isn't known: a flag the jvm doesn't know stops it from starting
+pub fn memory_arguments(config: &MemoryConfig, major: Option<u32>, variables: &Variables) -> Vec<String> {
+    let mut arguments = MEMORY_ARGUMENTS.iter().map(|a| substitute(a, variables)).collect::<Vec<_>>();
+    if let Some(major) = major {
+        arguments.extend(gc_flags(config.gc, major));
+    }
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_the_heap_from_the_machine() {
+        let default = MemoryConfig::default();
+        let inverted = MemoryConfig { min_mb: 2048, max_mb: 1024, ..Default::default() };
+        //(config, total, available) -> (initial, max)
+        let cases = [
+            (&default, 2048, 1024, (1024, 1024)),
+            (&default, 4096, 4096, (1024, 2048)),
+            (&default, 8192, 2048, (1024, 1843)),
+            (&default, 16384, 16000, (1024, 8192)),
+            (&default, 65536, 60000, (1024, 8192)),
+            (&default, 8192, 0, (1024, 1024)),
+            (&inverted, 16384, 16000, (2048, 2048)),
+        ];
+        for (config, total, available, expected) in cases {
+            let heap = compute_heap(config, total, available);
+            assert_eq!((heap.initial_mb, heap.max_mb), expected, "{} MB total, {} MB available", total, available);
+        }
+    }
+
+    #[test]
+    fn picks_the_gc_flags_the_java_knows() {
+        let cases: [(GcPreset, u32, &[&str]); 10] = [
+            (GcPreset::None, 8, &[]),
+            (GcPreset::None, 21, &[]),
+            (GcPreset::G1, 8, &["-XX:+UseG1GC", "-XX:G1NewSizePercent=20"]),
+            (GcPreset::G1, 21, &["-XX:+UseG1GC", "-XX:G1NewSizePercent=20"]),
+            (GcPreset::Aikar, 8, &["-XX:+UseG1GC", "-XX:+AlwaysPreTouch"]),
+            (GcPreset::Aikar, 21, &["-XX:+UseG1GC", "-XX:+AlwaysPreTouch"]),
+            (GcPreset::Zgc, 8, &["-XX:+UseG1GC"]),
+            (GcPreset::Zgc, 11, &["-XX:+UseG1GC"]),
+            (GcPreset::Zgc, 17, &["-XX:+UseZGC"]),
+            (GcPreset::Zgc, 21, &["-XX:+UseZGC", "-XX:+ZGenerational"]),
+        ];
+        for (preset, major, expected) in cases {
+            let flags = gc_flags(preset, major);
+            assert_eq!(flags.is_empty(), expected.is_empty(), "{:?} on java {}", preset, major);
+            assert!(expected.iter().all(|flag| flags.iter().any(|f| f == flag)), "{:?} on java {}: {:?}", preset, major, flags);
+        }
+        assert!(!gc_flags(GcPreset::Zgc, 17).iter().any(|f| f.contains("G1") || f == "-XX:+ZGenerational"));
+        assert!(!gc_flags(GcPreset::Zgc, 8).iter().any(|f| f.contains("ZGC")));
+
+        let config = MemoryConfig { gc: GcPreset::Zgc, ..Default::default() };
+        let variables = Heap { initial_mb: 1024, max_mb: 4096 }.variables().into_iter().collect::<Variables>();
+        assert_eq!(memory_arguments(&config, None, &variables), ["-Xms1024M", "-Xmx4096M"]);
+        assert_eq!(memory_arguments(&config, Some(21), &variables).len(), 4);
+    }
+}
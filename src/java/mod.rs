@@ -0,0 +1,74 @@
+mod runtime;
+mod discovery;
+mod memory;
+pub use runtime::*;
+pub use discovery::*;
+pub use memory::*;
+
+use core::fmt;
+use std::path::{Path, PathBuf};
+use crate::config::JavaConfig;
+use crate::download::DownloadError;
+use crate::{env, log, network};
+
+pub enum JavaError {
+    Download(DownloadError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Lzma(String),
+    NoRuntime(String),
+}
+
+impl fmt::Display for JavaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JavaError::Download(e) => write!(f, "Download error: {}", e),
+            JavaError::Io(e) => write!(f, "IO error: {}", e),
+            JavaError::Json(e) => write!(f, "JSON error: {}", e),
+            JavaError::Lzma(e) => write!(f, "LZMA error: {}", e),
+            JavaError::NoRuntime(e) => write!(f, "No java runtime available: {}", e),
+        }
+    }
+}
+
+impl From<DownloadError> for JavaError {
+    fn from(e: DownloadError) -> Self {
+        JavaError::Download(e)
+    }
+}
+
+//on windows, run the javaw next to java.exe so no console shows up. Discovered and provisioned runtimes
+//alike go through here
+fn windowless(java: &Path) -> PathBuf {
+    let javaw = java.with_file_name("javaw.exe");
+    if cfg!(target_os = "windows") && javaw.is_file() {
+        javaw
+    } else {
+        java.to_path_buf()
+    }
+}
+
+//java used for a launch: the configured one, else the provisioned runtime, else the best installed match
+pub async fn select_java<F>(config: &JavaConfig, component: &str, major: u32, callback_send_delta: F) -> Result<PathBuf, JavaError>
+where
+    F: Fn(u32) -> bool
+{
+    if let Some(path) = &config.path {
+        return Ok(PathBuf::from(path));
+    }
+    let runtimes_dir = Path::new(env::RUNTIME_DIR);
+    //offline, the runtimes provisioned earlier are found by the discovery below
+    if config.provision && !network::is_offline() {
+        match provision_runtime(&config.runtime_manifest_url, component, runtimes_dir, callback_send_delta).await {
+            Ok(java) => return Ok(java),
+            Err(e @ JavaError::Download(DownloadError::DownloadStopped)) => return Err(e),
+            Err(e) => log!("WARN", component, "runtime provisioning failed, looking for an installed java: {}", e),
+        }
+    }
+    let mut cache = JavaCache::load(env::JAVA_CACHE_PATH);
+    let installations = cache.discover(runtimes_dir);
+    cache.save(env::JAVA_CACHE_PATH);
+    select_best(&installations, major)
+        .map(|java| windowless(&java.path))
+        .ok_or_else(|| JavaError::NoRuntime(format!("java {}", major)))
+}
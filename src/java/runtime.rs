@@ -0,0 +1,319 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use futures_util::StreamExt;
+use sha1::{Digest, Sha1};
+use crate::download::{download_sized, file_to_hash, make_link, set_executable, DownloadError, File};
+use crate::{cache, env, log, network, peer};
+use super::{executable_name, windowless, JavaError};
+
+#[derive(Deserialize, Debug)]
+pub struct RuntimeDownload {
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RuntimeEntry {
+    pub manifest: RuntimeDownload,
+}
+
+//all.json: platform -> component -> available builds
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct RuntimeIndex {
+    pub platforms: HashMap<String, HashMap<String, Vec<RuntimeEntry>>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RuntimeFileDownloads {
+    pub raw: RuntimeDownload,
+    pub lzma: Option<RuntimeDownload>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RuntimeFile {
+    File {
+        #[serde(default)]
+        executable: bool,
+        downloads: RuntimeFileDownloads,
+    },
+    Directory,
+    Link {
+        target: String,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RuntimeManifest {
+    pub files: BTreeMap<String, RuntimeFile>,
+}
+
+//key of the current platform in all.json
+pub fn runtime_platform() -> &'static str {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("windows", "x86") => "windows-x86",
+        ("windows", "aarch64") => "windows-arm64",
+        ("windows", _) => "windows-x64",
+        ("macos", "aarch64") => "mac-os-arm64",
+        ("macos", _) => "mac-os",
+        (_, "x86") => "linux-i386",
+        _ => "linux",
+    }
+}
+
+//java binary inside a runtime
+pub fn java_executable(runtime_dir: &Path) -> PathBuf {
+    if cfg!(target_os = "macos") {
+        runtime_dir.join("jre.bundle/Contents/Home/bin/java")
+    } else {
+        runtime_dir.join("bin").join(executable_name())
+    }
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, JavaError> {
+    let resp = network::get_json(url).await.map_err(DownloadError::Reqwest)?;
+    let bytes = network::body(resp).await?;
+    serde_json::from_slice(&bytes).map_err(JavaError::Json)
+}
+
+async fn fetch_from(url: &str, sha1: &str) -> Result<Vec<u8>, DownloadError> {
+    let _connection = network::connection(url).await;
+    let resp = network::get(url).await.and_then(|r| r.error_for_status()).map_err(DownloadError::Reqwest)?;
+    let bytes = network::body(resp).await?;
+    if hex::encode(Sha1::digest(&bytes)) != sha1 {
+        return Err(DownloadError::HashMismatch(url.to_string()));
+    }
+    Ok(bytes)
+}
+
+async fn fetch_bytes(download: &RuntimeDownload) -> Result<Vec<u8>, JavaError> {
+    let mut last_error = DownloadError::NotFound(download.url.clone());
+    for url in network::candidates(std::slice::from_ref(&download.url)) {
+        match fetch_from(&url, &download.sha1).await {
+            Err(e) if e.is_remote() => {
+                log!("WARN", url, "{}", e);
+                if !matches!(e, DownloadError::HashMismatch(_)) {
+                    network::mark_unhealthy(&url);
+                }
+                last_error = e;
+            }
+            result => return Ok(result?),
+        }
+    }
+    Err(last_error.into())
+}
+
+//same, into a file: large payloads are fetched in ranges
+async fn fetch_to(download: &RuntimeDownload, path: &Path) -> Result<(), JavaError> {
+    let mut last_error = DownloadError::NotFound(download.url.clone());
+    for url in network::candidates(std::slice::from_ref(&download.url)) {
+        let result = match download_sized(&url, path, download.size, |_| false).await {
+            Ok(()) if hex::encode(file_to_hash::<Sha1, _>(path)?) == download.sha1 => Ok(()),
+            Ok(()) => Err(DownloadError::HashMismatch(url.clone())),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if e.is_remote() => {
+                log!("WARN", url, "{}", e);
+                if !matches!(e, DownloadError::HashMismatch(_)) {
+                    network::mark_unhealthy(&url);
+                }
+                last_error = e;
+            }
+            result => return Ok(result?),
+        }
+    }
+    Err(last_error.into())
+}
+
+fn decompress_lzma(from: &Path, to: &Path, name: &str) -> Result<(), JavaError> {
+    use std::io::Write;
+    let mut input = std::io::BufReader::new(std::fs::File::open(from).map_err(JavaError::Io)?);
+    let mut output = std::io::BufWriter::new(cache::create_file(to).map_err(JavaError::Io)?);
+    lzma_rs::lzma_decompress(&mut input, &mut output).map_err(|e| JavaError::Lzma(format!("{}: {}", name, e)))?;
+    output.flush().map_err(JavaError::Io)
+}
+
+//the raw file from the first peer that has it, false when none does
+async fn from_peers(raw: &File, root: &str) -> Result<bool, JavaError> {
+    let path = Path::new(root).join(&raw.path);
+    for url in peer::urls(&raw.hash) {
+        match download_sized(&url, &path, raw.size, |_| false).await {
+            Ok(()) if raw.verify(root).await? => return Ok(true),
+            Ok(()) => log!("WARN", url, "{}", DownloadError::HashMismatch(raw.path.clone())),
+            Err(e) if e.is_not_found() => log!("INFO", url, "{}", e),
+            Err(e) if e.is_remote() => {
+                log!("WARN", url, "{}", e);
+                network::mark_unhealthy(&url);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(false)
+}
+
+//the lzma variant is preferred when available, the result is checked against the raw hash
+async fn install_file(root: &Path, name: &str, downloads: &RuntimeFileDownloads, executable: bool) -> Result<(), JavaError> {
+    let raw = File {
+        url: downloads.raw.url.clone(),
+        size: downloads.raw.size,
+        hash: downloads.raw.sha1.clone(),
+        path: name.to_string(),
+        ..Default::default()
+    };
+    let root_str = root.to_string_lossy();
+    let path = root.join(name);
+    let mut present = path.exists() && raw.verify(&root_str).await?;
+    if let (false, Some(store)) = (present, cache::shared()) {
+        present = store.restore(&raw.hash, &path) && raw.verify(&root_str).await?;
+        if !present {
+            store.forget(&raw.hash);
+        }
+    }
+    if !present {
+        match &downloads.lzma {
+            //peers only have the raw file, asked first; upstream the compressed payload saves bandwidth
+            Some(_) if from_peers(&raw, &root_str).await? => {
+                if let Some(store) = cache::shared() {
+                    store.insert(&path, &raw.hash);
+                }
+            }
+            Some(lzma) => {
+                let mut compressed = path.as_os_str().to_owned();
+                compressed.push(".lzma");
+                let compressed = PathBuf::from(compressed);
+                fetch_to(lzma, &compressed).await?;
+                let decompressed = decompress_lzma(&compressed, &path, name);
+                let _ = std::fs::remove_file(&compressed);
+                decompressed?;
+                if !raw.verify(&root_str).await? {
+                    return Err(DownloadError::HashMismatch(name.to_string()).into());
+                }
+                if let Some(store) = cache::shared() {
+                    store.insert(&path, &raw.hash);
+                }
+            }
+            _ => raw.ensure(&root_str).await?,
+        }
+    }
+    if executable {
+        set_executable(&path)?;
+    }
+    Ok(())
+}
+
+//download the runtime <component> into <runtimes_dir>/<component>, returns the java executable
+pub async fn provision_runtime<F>(manifest_url: &str, component: &str, runtimes_dir: &Path, callback_send_delta: F) -> Result<PathBuf, JavaError>
+where
+    F: Fn(u32) -> bool
+{
+    let index: RuntimeIndex = fetch_json(manifest_url).await?;
+    let platform = runtime_platform();
+    let entry = index.platforms
+        .get(platform)
+        .and_then(|components| components.get(component))
+        .and_then(|entries| entries.first())
+        .ok_or_else(|| JavaError::NoRuntime(format!("{} for {}", component, platform)))?;
+    let manifest: RuntimeManifest = serde_json::from_slice(&fetch_bytes(&entry.manifest).await?).map_err(JavaError::Json)?;
+    let root = runtimes_dir.join(component);
+    log!("INFO", component, "provisioning java runtime in {}", root.display());
+
+    for (name, _) in manifest.files.iter().filter(|(_, f)| matches!(f, RuntimeFile::Directory)) {
+        std::fs::create_dir_all(root.join(name)).map_err(JavaError::Io)?;
+    }
+    let files = manifest.files.iter().filter_map(|(name, file)| match file {
+        RuntimeFile::File { executable, downloads } => Some((name, downloads, *executable)),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let total: u64 = files.iter().map(|(_, downloads, _)| downloads.raw.size).sum();
+    let mut stream = futures_util::stream::iter(files.into_iter().map(|(name, downloads, executable)| {
+        let root = &root;
+        async move {
+            install_file(root, name, downloads, executable).await.map(|_| downloads.raw.size)
+        }
+    })).buffer_unordered(env::DOWNLOAD_CONCURRENCY);
+    let mut current_bytes = 0;
+    let mut current_percent = 0;
+    while let Some(size) = stream.next().await {
+        current_bytes += size?;
+        if total == 0 {
+            continue;
+        }
+        let next_percent = current_bytes * 100 / total;
+        if next_percent > current_percent {
+            if callback_send_delta((next_percent - current_percent) as u32) {
+                return Err(DownloadError::DownloadStopped.into());
+            }
+            current_percent = next_percent;
+        }
+    }
+    for (name, file) in &manifest.files {
+        if let RuntimeFile::Link { target } = file {
+            make_link(&root.join(name), target)?;
+        }
+    }
+    Ok(windowless(&java_executable(&root)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn download(base: &str, path: &str, body: &[u8]) -> serde_json::Value {
+        serde_json::json!({ "sha1": hex::encode(Sha1::digest(body)), "size": body.len(), "url": format!("{}{}", base, path) })
+    }
+
+    #[tokio::test]
+    async fn provisions_a_runtime() {
+        let modules = b"java.base java.desktop java.logging".repeat(64);
+        let mut lzma = Vec::new();
+        lzma_rs::lzma_compress(&mut &modules[..], &mut lzma).unwrap();
+        let server = crate::test_server::TestServer::start_with(|base| {
+            let manifest = serde_json::to_vec(&serde_json::json!({ "files": {
+                "bin": { "type": "directory" },
+                "bin/java": { "type": "file", "executable": true, "downloads": { "raw": download(base, "/java", b"#!java") } },
+                "lib/modules": { "type": "file", "downloads": {
+                    "raw": download(base, "/modules", &modules),
+                    "lzma": download(base, "/modules.lzma", &lzma),
+                }},
+                "release": { "type": "file", "downloads": { "raw": download(base, "/release", b"JAVA_VERSION=\"17.0.8\"") } },
+                "lib/release": { "type": "link", "target": "../release" },
+            }})).unwrap();
+            let all = serde_json::json!({ runtime_platform(): {
+                "java-runtime-gamma": [{ "manifest": download(base, "/manifest.json", &manifest) }],
+            }});
+            vec![
+                (String::from("/all.json"), serde_json::to_vec(&all).unwrap()),
+                (String::from("/manifest.json"), manifest),
+                (String::from("/java"), b"#!java".to_vec()),
+                (String::from("/modules.lzma"), lzma),
+                (String::from("/release"), b"JAVA_VERSION=\"17.0.8\"".to_vec()),
+            ]
+        }).await;
+        let runtimes = tempfile::tempdir().unwrap();
+
+        let java = provision_runtime(&server.url("/all.json"), "java-runtime-gamma", runtimes.path(), |_| false).await.ok().unwrap();
+
+        let root = runtimes.path().join("java-runtime-gamma");
+        assert_eq!(java, windowless(&java_executable(&root)));
+        assert_eq!(std::fs::read(root.join("bin/java")).unwrap(), b"#!java");
+        assert_eq!(std::fs::read(root.join("lib/modules")).unwrap(), modules);
+        assert!(!root.join("lib/modules.lzma").exists());
+        assert!(server.requests("/modules ").is_empty());
+        assert_eq!(std::fs::read_to_string(root.join("lib/release")).unwrap(), "JAVA_VERSION=\"17.0.8\"");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(root.join("bin/java")).unwrap().permissions().mode() & 0o111, 0o111);
+            assert_eq!(std::fs::read_link(root.join("lib/release")).unwrap(), Path::new("../release"));
+        }
+
+        //a second run finds everything in place
+        let before = server.requests("").len();
+        provision_runtime(&server.url("/all.json"), "java-runtime-gamma", runtimes.path(), |_| false).await.ok().unwrap();
+        assert_eq!(server.requests("").len(), before + 2);
+    }
+}
@@ -1,6 +1,7 @@
 pub struct Launcher {
     pub program: String,
     pub arguments: Vec<String>,
+    pub working_dir: Option<std::path::PathBuf>,
 }
 
 impl Launcher {
@@ -9,6 +10,9 @@ impl Launcher {
         if !self.arguments.is_empty() {
             command.args(&self.arguments);
         }
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(working_dir);
+        }
         command.spawn()?;
         Ok(())
     }
@@ -1,6 +1,9 @@
 #![cfg_attr(all(target_os = "windows", not(debug_assertions)), windows_subsystem = "windows")]
 
 use std::path::Path;
+use config::LaunchMode;
+use instance::{Instance, LoaderKind};
+use launcher::Launcher;
 
 mod helper;
 mod download;
@@ -8,9 +11,117 @@ mod env;
 mod launcher;
 mod window;
 mod log;
+mod version;
+mod config;
+mod java;
+mod direct;
+mod cli;
+mod instance;
+mod profiles;
+mod modpack;
+mod maven;
+mod cache;
+mod network;
+mod peer;
+mod signature;
+mod publish;
+#[cfg(test)]
+mod test_server;
 
 #[tokio::main]
 async fn main() {
+    if let Err(e) = network::init(&env::CONFIG) {
+        return log!("ERROR", "invalid http settings in {}: {}", env::CONFIG_PATH, e);
+    }
+    if env::CONFIG.cache {
+        cache::init(cache::Store::new(env::CACHE_DIR));
+    }
+    let (instance, offline) = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli::Command::Launch { instance, offline }) => (instance, offline),
+        Ok(command) => {
+            attach_console();
+            if let Err(e) = cli::run(command).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(usage) => {
+            attach_console();
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    };
+    let instance = match Instance::select(instance.as_deref()) {
+        Ok(instance) => instance,
+        Err(e) => return log!("ERROR", "while selecting instance: {}", e),
+    };
+    if network::resolve_offline(env::CONFIG.offline, offline, &env::CONFIG.version_manifest_url).await {
+        log!("WARN", "Offline mode, nothing will be downloaded");
+        network::set_offline(true);
+    } else {
+        peer::init(peer::discover(&env::CONFIG.peers).await);
+    }
+    tokio::spawn(network::watch_bandwidth_limit(Path::new(env::BANDWIDTH_PATH)));
+    let prefetch = (env::CONFIG.prefetch && !network::is_offline())
+        .then(|| tokio::spawn(network::background(prefetch_others(instance.name.clone()))));
+    match env::CONFIG.mode {
+        LaunchMode::Official => launch_official(&instance).await,
+        LaunchMode::Direct => launch_direct(&instance).await,
+    }
+    if let Some(prefetch) = prefetch {
+        let _ = prefetch.await;
+    }
+}
+
+//release builds have no console of their own: commands print to the one they were typed in, if any
+#[cfg(windows)]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    //fails when started from the explorer, there is nothing to print to then
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+//updates of the other instances, fetched into the cache while this one launches
+async fn prefetch_others(launched: String) {
+    let instances = match Instance::list() {
+        Ok(instances) => instances,
+        Err(e) => return log!("WARN", "prefetch: {}", e),
+    };
+    for instance in instances.iter().filter(|i| i.name != launched) {
+        match instance.prefetch().await {
+            Ok(0) => {}
+            Ok(count) => log!("INFO", instance.name, "prefetched {} files", count),
+            Err(e) => log!("WARN", instance.name, "prefetch failed: {}", e),
+        }
+    }
+}
+
+fn execute(launcher: &Launcher) {
+    log!("INFO", "Executing launcher!");
+    match launcher.execute() {
+        Ok(_) => log!("OK", "Launcher executed!"),
+        Err(e) => log!("ERROR", "Error while executing launcher: {}", e),
+    }
+}
+
+async fn launch_direct(instance: &Instance) {
+    log!("INFO", instance.name, "Preparing direct launch of {}...", instance.version());
+    let window_info = helper::WindowInfo::new(0..100);
+    let launcher = direct::prepare(&env::CONFIG, instance, &window_info).await;
+    window_info.stop();
+    match launcher {
+        Ok(launcher) => execute(&launcher),
+        Err(e) => log!("ERROR", instance.name, "while preparing: {}", e),
+    }
+}
+
+async fn launch_official(instance: &Instance) {
     let path = Path::new(env::PATH);
     let filename = path
         .file_name()
@@ -24,8 +135,12 @@ async fn main() {
             log!("OK", filename, "File exists! Skip download!");
             break 'dl_file;
         }
+        if network::is_offline() {
+            log!("ERROR", filename, "{}", download::DownloadError::missing(path));
+            return;
+        }
         log!("INFO", filename, "Downloading...");
-        match download::download_file(env::URL, env::PATH, |delta: u32| -> bool {
+        match download::download_first(&env::CONFIG.launcher_urls, env::PATH, |delta: u32| -> bool {
             if let Err(_) = window_info.update(window::Signal::AdvanceDelta(delta)) {
                 log!("WARN", "Window closed!");
                 return true;
@@ -46,12 +161,35 @@ async fn main() {
             }
         }
     }
-    window_info.stop();
-    log!("INFO", "Executing launcher!");
-    if let Some(launcher) = &*env::LAUNCHER {
-        match launcher.execute() {
-            Ok(_) => log!("OK", "Launcher executed!"),
-            Err(e) => log!("ERROR", "Error while executing launcher: {}", e),
+    if let Err(e) = instance.sync_files(|delta: u32| window_info.update(window::Signal::AdvanceDelta(delta)).is_err()).await {
+        log!("ERROR", instance.name, "while syncing files: {}", e);
+        return;
+    }
+    //aliases like "latest-release" are resolved as direct launches do, the loader and the profiles need the real id
+    let versions_dir = Path::new(env::WORK_DIR).join("versions");
+    let manifest = match version::VersionManifest::load(&env::CONFIG.version_manifest_url, &versions_dir.join("version_manifest_v2.json")).await {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            log!("WARN", instance.name, "version aliases left unresolved: {}", e);
+            None
+        }
+    };
+    let game_version = manifest.as_ref().map_or(instance.version(), |m| m.resolve(instance.version()));
+    //the official launcher resolves "inheritsFrom" and the loader libraries by itself, it only needs the profile.
+    //forge's installer processors are only run by direct launches
+    if let Some(loader) = &instance.config.loader {
+        if matches!(loader.kind, LoaderKind::Forge | LoaderKind::NeoForge) {
+            log!("ERROR", instance.name, "forge and neoforge instances need \"mode\": \"direct\" in {}", env::CONFIG_PATH);
+            return;
         }
+        if let Err(e) = version::install_loader(&env::CONFIG.loaders, loader, game_version, &versions_dir).await {
+            log!("ERROR", instance.name, "while installing the mod loader: {}", e);
+            return;
+        }
+    }
+    if let Err(e) = profiles::update_profiles(instance, manifest.as_ref()) {
+        log!("ERROR", instance.name, "while writing launcher profiles: {}", e);
     }
+    window_info.stop();
+    execute(&env::launcher(instance));
 }
\ No newline at end of file
@@ -0,0 +1,175 @@
+use std::path::Path;
+use core::fmt;
+use futures_util::StreamExt;
+use sha1::Sha1;
+use crate::download::{file_to_hash, DownloadError, File};
+use crate::{env, log, network};
+
+//"group:artifact:version[:classifier][@extension]", the name of every library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coordinates {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    pub classifier: Option<String>,
+    pub extension: String,
+}
+
+impl Coordinates {
+    pub fn parse(name: &str) -> Option<Coordinates> {
+        let (name, extension) = name.split_once('@').unwrap_or((name, "jar"));
+        let mut parts = name.split(':');
+        let (group, artifact, version) = (parts.next()?, parts.next()?, parts.next()?);
+        let classifier = parts.next().map(str::to_string);
+        if parts.next().is_some() || [group, artifact, version, extension].iter().any(|p| p.is_empty()) {
+            return None;
+        }
+        Some(Coordinates {
+            group: group.to_string(),
+            artifact: artifact.to_string(),
+            version: version.to_string(),
+            classifier,
+            extension: extension.to_string(),
+        })
+    }
+
+    //"group/path/artifact/version/artifact-version[-classifier].extension", relative to a repository
+    pub fn path(&self) -> String {
+        let file = match &self.classifier {
+            Some(classifier) => format!("{}-{}-{}.{}", self.artifact, self.version, classifier, self.extension),
+            None => format!("{}-{}.{}", self.artifact, self.version, self.extension),
+        };
+        format!("{}/{}/{}/{}", self.group.replace('.', "/"), self.artifact, self.version, file)
+    }
+
+    //"group:artifact[:classifier]", what two versions of the same library share
+    pub fn key(&self) -> String {
+        match &self.classifier {
+            Some(classifier) => format!("{}:{}:{}", self.group, self.artifact, classifier),
+            None => format!("{}:{}", self.group, self.artifact),
+        }
+    }
+}
+
+impl fmt::Display for Coordinates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.group, self.artifact, self.version)?;
+        if let Some(classifier) = &self.classifier {
+            write!(f, ":{}", classifier)?;
+        }
+        if self.extension != "jar" {
+            write!(f, "@{}", self.extension)?;
+        }
+        Ok(())
+    }
+}
+
+//a library known by its coordinates only, its repository and hash are found at download time
+#[derive(Debug, Clone)]
+pub struct MavenArtifact {
+    pub coordinates: Coordinates,
+    //repository named by the library itself, tried before the configured ones
+    pub repository: Option<String>,
+    pub sha1: Option<String>,
+    pub size: u64,
+}
+
+fn artifact_url(repository: &str, coordinates: &Coordinates) -> String {
+    format!("{}/{}", repository.trim_end_matches('/'), coordinates.path())
+}
+
+//repositories publish "<artifact>.sha1" next to each artifact, sometimes followed by the file name
+async fn fetch_sha1(client: &reqwest::Client, url: &str) -> Result<String, reqwest::Error> {
+    let text = client.get(format!("{}.sha1", url)).send().await?.error_for_status()?.text().await?;
+    Ok(text.split_whitespace().next().unwrap_or_default().to_lowercase())
+}
+
+impl MavenArtifact {
+    //the first repository holding the artifact, as a file of the download engine
+    pub async fn resolve(&self, client: &reqwest::Client, repositories: &[String]) -> Result<File, DownloadError> {
+        let path = self.coordinates.path();
+        let candidates = self.repository.iter().chain(repositories.iter().filter(|r| Some(*r) != self.repository.as_ref()));
+        for repository in candidates {
+            let url = artifact_url(repository, &self.coordinates);
+            let hash = match &self.sha1 {
+                //the library names its repository and hash, nothing to ask
+                Some(sha1) if Some(repository) == self.repository.as_ref() => Ok(sha1.clone()),
+                Some(sha1) => client.head(&url).send().await
+                    .and_then(|r| r.error_for_status())
+                    .map(|_| sha1.clone()),
+                None => fetch_sha1(client, &url).await,
+            };
+            match hash {
+                Ok(hash) => return Ok(File { url, size: self.size, hash, path, ..Default::default() }),
+                Err(e) => log!("INFO", self.coordinates, "not in {}: {}", repository, e),
+            }
+        }
+        Err(DownloadError::NotFound(self.coordinates.to_string()))
+    }
+
+    //offline: the copy under libraries_dir. A library without a sha1 gets the hash of that very copy, so
+    //it is only checked for presence: it was verified against the repository's .sha1 when downloaded,
+    //a later change to it goes unnoticed until the next online launch
+    pub fn local(&self, libraries_dir: &Path) -> File {
+        let path = self.coordinates.path();
+        let hash = self.sha1.clone().unwrap_or_else(|| {
+            file_to_hash::<Sha1, _>(libraries_dir.join(&path)).map(hex::encode).unwrap_or_default()
+        });
+        File { url: String::new(), size: self.size, hash, path, ..Default::default() }
+    }
+}
+
+//resolve every artifact, keeping their order. Offline, the local copies are used instead
+pub async fn resolve_all(artifacts: &[MavenArtifact], repositories: &[String], libraries_dir: &Path) -> Result<Vec<File>, DownloadError> {
+    if network::is_offline() {
+        return Ok(artifacts.iter().map(|a| a.local(libraries_dir)).collect());
+    }
+    let client = network::client();
+    futures_util::stream::iter(artifacts.iter().map(|a| a.resolve(client, repositories)))
+        .buffered(env::DOWNLOAD_CONCURRENCY)
+        .collect::<Vec<_>>().await
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+
+    #[test]
+    fn parses_coordinates() {
+        let coordinates = Coordinates::parse("de.oceanlabs.mcp:mcp_config:1.20.1-20230612.114412:mappings@txt").unwrap();
+        assert_eq!(coordinates.path(), "de/oceanlabs/mcp/mcp_config/1.20.1-20230612.114412/mcp_config-1.20.1-20230612.114412-mappings.txt");
+        assert_eq!(coordinates.key(), "de.oceanlabs.mcp:mcp_config:mappings");
+        assert_eq!(coordinates.to_string(), "de.oceanlabs.mcp:mcp_config:1.20.1-20230612.114412:mappings@txt");
+
+        let coordinates = Coordinates::parse("org.ow2.asm:asm:9.6").unwrap();
+        assert_eq!(coordinates.path(), "org/ow2/asm/asm/9.6/asm-9.6.jar");
+        assert_eq!(coordinates.key(), "org.ow2.asm:asm");
+
+        assert!(Coordinates::parse("org.ow2.asm:asm").is_none());
+        assert!(Coordinates::parse("a:b:c:d:e").is_none());
+    }
+
+    #[tokio::test]
+    async fn tries_repositories_in_order_and_reads_sha1_files() {
+        let first = TestServer::start(vec![]).await;
+        let second = TestServer::start(vec![
+            ("/org/ow2/asm/asm/9.6/asm-9.6.jar", b"asm".to_vec()),
+            ("/org/ow2/asm/asm/9.6/asm-9.6.jar.sha1", b"ABCDEF  asm-9.6.jar\n".to_vec()),
+        ]).await;
+        let artifact = MavenArtifact {
+            coordinates: Coordinates::parse("org.ow2.asm:asm:9.6").unwrap(),
+            repository: None,
+            sha1: None,
+            size: 0,
+        };
+
+        let files = resolve_all(std::slice::from_ref(&artifact), &[first.url(""), second.url("/")], Path::new("libraries")).await.ok().unwrap();
+
+        assert_eq!(files[0].url, second.url("/org/ow2/asm/asm/9.6/asm-9.6.jar"));
+        assert_eq!(files[0].hash, "abcdef");
+        assert!(resolve_all(&[artifact], &[first.url("")], Path::new("libraries")).await.is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use serde::Deserialize;
+use std::io::{Read, Seek};
+use std::path::Path;
+use futures_util::StreamExt;
+use crate::config::CurseForgeConfig;
+use crate::download::{DownloadError, File};
+use crate::instance::{Loader, LoaderKind};
+use crate::{env, log, network};
+use super::{extract_overrides, read_json, safe_path, ManualDownload, ModpackError, PackInfo};
+
+//sha1 in the "algo" field of the api's file hashes, 2 is md5
+const SHA1_ALGO: u32 = 1;
+
+#[derive(Deserialize, Debug)]
+pub struct ModLoader {
+    //"forge-47.2.0", "neoforge-20.4.80", "fabric-0.15.7"...
+    pub id: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CurseForgeMinecraft {
+    pub version: String,
+    #[serde(default)]
+    pub mod_loaders: Vec<ModLoader>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CurseForgeFile {
+    #[serde(rename = "projectID")]
+    pub project_id: u64,
+    #[serde(rename = "fileID")]
+    pub file_id: u64,
+    #[serde(default = "required_default")]
+    pub required: bool,
+}
+
+fn required_default() -> bool {
+    true
+}
+
+//manifest.json at the root of a CurseForge modpack zip
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CurseForgeManifest {
+    pub manifest_type: String,
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    pub minecraft: CurseForgeMinecraft,
+    pub files: Vec<CurseForgeFile>,
+    #[serde(default = "overrides_default")]
+    pub overrides: String,
+}
+
+fn overrides_default() -> String {
+    String::from("overrides")
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FileHash {
+    pub value: String,
+    pub algo: u32,
+}
+
+//data of GET /v1/mods/{projectID}/files/{fileID}
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfo {
+    pub file_name: String,
+    //null when the author disabled third-party distribution
+    pub download_url: Option<String>,
+    pub file_length: u64,
+    #[serde(default)]
+    pub hashes: Vec<FileHash>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FileResponse {
+    data: FileInfo,
+}
+
+impl CurseForgeManifest {
+    pub fn loader(&self) -> Option<Loader> {
+        let loaders = &self.minecraft.mod_loaders;
+        let loader = loaders.iter().find(|l| l.primary).or(loaders.first())?;
+        let (kind, version) = loader.id.split_once('-')?;
+        let kind = match kind {
+            "forge" => LoaderKind::Forge,
+            "neoforge" => LoaderKind::NeoForge,
+            "fabric" => LoaderKind::Fabric,
+            "quilt" => LoaderKind::Quilt,
+            _ => return None,
+        };
+        Some(Loader { kind, version: version.to_string() })
+    }
+}
+
+fn manual(file: &CurseForgeFile, name: Option<&str>) -> ManualDownload {
+    ManualDownload {
+        name: name.map_or_else(|| format!("project {} file {}", file.project_id, file.file_id), str::to_string),
+        page: format!("https://www.curseforge.com/projects/{}", file.project_id),
+        target: String::from("mods"),
+    }
+}
+
+//None when the api can't tell where the file is, it is then left for a manual download
+async fn file_info(client: &reqwest::Client, config: &CurseForgeConfig, file: &CurseForgeFile) -> Option<FileInfo> {
+    let url = format!("{}/v1/mods/{}/files/{}", config.api_url.trim_end_matches('/'), file.project_id, file.file_id);
+    let mut request = client.get(&url);
+    if let Some(key) = &config.api_key {
+        request = request.header("x-api-key", key);
+    }
+    let response = match request.send().await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            log!("WARN", url, "api answered {}", response.status());
+            return None;
+        }
+        Err(e) => {
+            log!("WARN", url, "{}", e);
+            return None;
+        }
+    };
+    match response.json::<FileResponse>().await {
+        Ok(response) => Some(response.data),
+        Err(e) => {
+            log!("WARN", url, "invalid answer: {}", e);
+            None
+        }
+    }
+}
+
+//downloads a file into mods/, or tells why it has to be fetched by hand
+async fn install_file(client: &reqwest::Client, config: &CurseForgeConfig, file: &CurseForgeFile, game_dir: &Path) -> Result<Option<ManualDownload>, ModpackError> {
+    let Some(info) = file_info(client, config, file).await else {
+        return Ok(Some(manual(file, None)));
+    };
+    let sha1 = info.hashes.iter().find(|h| h.algo == SHA1_ALGO);
+    let (Some(url), Some(sha1)) = (&info.download_url, sha1) else {
+        return Ok(Some(manual(file, Some(&info.file_name))));
+    };
+    let path = Path::new("mods").join(safe_path(&info.file_name)?);
+    let download = File {
+        url: url.clone(),
+        size: info.file_length,
+        hash: sha1.value.clone(),
+        path: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+    //only this machine's failures stop the install, the cdn's leave the file to the user
+    match download.ensure(&game_dir.to_string_lossy()).await {
+        Ok(()) => Ok(None),
+        Err(e) if e.is_remote() || matches!(e, DownloadError::NotFound(_)) => {
+            log!("WARN", url, "{}", e);
+            Ok(Some(manual(file, Some(&info.file_name))))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+//resolve the files of a CurseForge zip through the api, download them into mods/ and apply the overrides.
+//files the api won't hand out are returned for the user to download by hand
+pub async fn install_curseforge<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, game_dir: &Path, config: &CurseForgeConfig) -> Result<PackInfo, ModpackError> {
+    let manifest: CurseForgeManifest = read_json(archive, "manifest.json")?;
+    if manifest.manifest_type != "minecraftModpack" {
+        return Err(ModpackError::Invalid(manifest.manifest_type));
+    }
+    log!("INFO", manifest.name, "installing {} into {}", manifest.version, game_dir.display());
+
+    let client = network::client();
+    let files = manifest.files.iter().filter(|f| f.required).collect::<Vec<_>>();
+    let mut stream = futures_util::stream::iter(files.into_iter().map(|file| install_file(client, config, file, game_dir)))
+        .buffer_unordered(env::DOWNLOAD_CONCURRENCY);
+    let mut manual_downloads = Vec::new();
+    while let Some(result) = stream.next().await {
+        if let Some(manual) = result? {
+            log!("WARN", manual.name, "manual download required: {}", manual.page);
+            manual_downloads.push(manual);
+        }
+    }
+    drop(stream);
+
+    extract_overrides(archive, &manifest.overrides, game_dir)?;
+    Ok(PackInfo {
+        name: manifest.name.clone(),
+        minecraft: Some(manifest.minecraft.version.clone()),
+        loader: manifest.loader(),
+        manual_downloads,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modpack::install_pack;
+    use crate::test_server::{zip_fixture, TestServer};
+    use sha1::{Digest, Sha1};
+
+    fn file_response(name: &str, url: Option<String>, bytes: &[u8]) -> Vec<u8> {
+        serde_json::json!({ "data": {
+            "fileName": name,
+            "downloadUrl": url,
+            "fileLength": bytes.len(),
+            "hashes": [
+                { "value": hex::encode(md5::Md5::digest(bytes)), "algo": 2 },
+                { "value": hex::encode(Sha1::digest(bytes)), "algo": 1 },
+            ],
+        }}).to_string().into_bytes()
+    }
+
+    fn fixture(files: serde_json::Value) -> Vec<u8> {
+        let manifest = serde_json::json!({
+            "manifestType": "minecraftModpack",
+            "manifestVersion": 1,
+            "name": "Fixture",
+            "version": "1.0.0",
+            "minecraft": { "version": "1.20.1", "modLoaders": [{ "id": "forge-47.2.0", "primary": true }] },
+            "files": files,
+            "overrides": "overrides",
+        });
+        zip_fixture(&[
+            ("manifest.json", manifest.to_string().as_bytes()),
+            ("overrides/config/a.txt", b"override"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn installs_files_overrides_and_lists_manual_downloads() {
+        let server = TestServer::start_with(|base| vec![
+            (String::from("/v1/mods/1/files/10"), file_response("a.jar", Some(format!("{}/files/a.jar", base)), b"mod a")),
+            (String::from("/v1/mods/2/files/20"), file_response("b.jar", None, b"mod b")),
+            (String::from("/files/a.jar"), b"mod a".to_vec()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.zip");
+        std::fs::write(&source, fixture(serde_json::json!([
+            { "projectID": 1, "fileID": 10, "required": true },
+            { "projectID": 2, "fileID": 20, "required": true },
+            { "projectID": 3, "fileID": 30, "required": false },
+        ]))).unwrap();
+        let game_dir = dir.path().join("game");
+        let config = CurseForgeConfig { api_url: server.url("/"), api_key: Some(String::from("key")) };
+
+        let info = install_pack(&source.to_string_lossy(), &game_dir, &config).await.ok().unwrap();
+
+        assert_eq!(std::fs::read(game_dir.join("mods/a.jar")).unwrap(), b"mod a");
+        assert_eq!(std::fs::read(game_dir.join("config/a.txt")).unwrap(), b"override");
+        assert_eq!(info.minecraft.as_deref(), Some("1.20.1"));
+        let loader = info.loader.unwrap();
+        assert_eq!((loader.kind, loader.version.as_str()), (LoaderKind::Forge, "47.2.0"));
+        let manual = info.manual_downloads.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(manual, ["b.jar"]);
+        assert!(server.requests("/v1/mods/1/files/10").iter().all(|r| r.to_ascii_lowercase().contains("x-api-key: key")));
+    }
+
+    #[tokio::test]
+    async fn files_the_cdn_fails_are_left_for_manual_download() {
+        let server = TestServer::start_with(|base| vec![
+            (String::from("/v1/mods/1/files/10"), file_response("gone.jar", Some(format!("{}/files/gone.jar", base)), b"mod a")),
+            (String::from("/v1/mods/2/files/20"), file_response("changed.jar", Some(format!("{}/files/changed.jar", base)), b"mod b")),
+            (String::from("/v1/mods/3/files/30"), b"not json".to_vec()),
+            (String::from("/files/changed.jar"), b"mod b, rebuilt".to_vec()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.zip");
+        std::fs::write(&source, fixture(serde_json::json!([
+            { "projectID": 1, "fileID": 10 },
+            { "projectID": 2, "fileID": 20 },
+            { "projectID": 3, "fileID": 30 },
+        ]))).unwrap();
+        let config = CurseForgeConfig { api_url: server.url(""), api_key: Some(String::from("secret")) };
+
+        let info = install_pack(&source.to_string_lossy(), &dir.path().join("game"), &config).await.ok().unwrap();
+
+        let mut manual = info.manual_downloads.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        manual.sort();
+        assert_eq!(manual, ["changed.jar", "gone.jar", "project 3 file 30"]);
+        assert_eq!(server.requests("/v1/mods/").len(), 3);
+        assert!(server.requests("/v1/mods/").iter().all(|r| r.to_ascii_lowercase().contains("x-api-key: secret")));
+    }
+
+    #[tokio::test]
+    async fn unknown_files_are_left_for_manual_download() {
+        let server = TestServer::start(vec![]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.zip");
+        std::fs::write(&source, fixture(serde_json::json!([{ "projectID": 4, "fileID": 40 }]))).unwrap();
+        let config = CurseForgeConfig { api_url: server.url(""), api_key: None };
+
+        let info = install_pack(&source.to_string_lossy(), &dir.path().join("game"), &config).await.ok().unwrap();
+
+        assert_eq!(info.manual_downloads.len(), 1);
+        assert_eq!(info.manual_downloads[0].page, "https://www.curseforge.com/projects/4");
+    }
+}
@@ -0,0 +1,145 @@
+mod mrpack;
+mod curseforge;
+pub use mrpack::*;
+pub use curseforge::*;
+
+use serde::de::DeserializeOwned;
+use std::io::{Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use core::fmt;
+use crate::config::CurseForgeConfig;
+use crate::download::DownloadError;
+use crate::instance::{Instance, InstanceConfig, InstanceError, Loader};
+use crate::{cache, env, log};
+
+pub enum ModpackError {
+    Download(DownloadError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Zip(zip::result::ZipError),
+    Instance(InstanceError),
+    Invalid(String),
+    UnsafePath(String),
+}
+
+impl fmt::Display for ModpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModpackError::Download(e) => write!(f, "Download error: {}", e),
+            ModpackError::Io(e) => write!(f, "IO error: {}", e),
+            ModpackError::Json(e) => write!(f, "JSON error: {}", e),
+            ModpackError::Zip(e) => write!(f, "Zip error: {}", e),
+            ModpackError::Instance(e) => write!(f, "{}", e),
+            ModpackError::Invalid(e) => write!(f, "Invalid modpack: {}", e),
+            ModpackError::UnsafePath(path) => write!(f, "Unsafe path in modpack: {}", path),
+        }
+    }
+}
+
+impl From<InstanceError> for ModpackError {
+    fn from(e: InstanceError) -> Self {
+        ModpackError::Instance(e)
+    }
+}
+
+impl From<DownloadError> for ModpackError {
+    fn from(e: DownloadError) -> Self {
+        ModpackError::Download(e)
+    }
+}
+
+//a file the pack needs but that its host refuses to hand out to third-party launchers
+pub struct ManualDownload {
+    pub name: String,
+    //page where the user can fetch it, the file goes to "target" in the game directory
+    pub page: String,
+    pub target: String,
+}
+
+//what an installed pack asks of its instance
+pub struct PackInfo {
+    pub name: String,
+    pub minecraft: Option<String>,
+    pub loader: Option<Loader>,
+    pub manual_downloads: Vec<ManualDownload>,
+}
+
+//a relative path staying inside the game directory
+fn safe_path(path: &str) -> Result<PathBuf, ModpackError> {
+    let relative = PathBuf::from(path);
+    if path.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(ModpackError::UnsafePath(path.to_string()));
+    }
+    Ok(relative)
+}
+
+fn read_json<R: Read + Seek, T: DeserializeOwned>(archive: &mut zip::ZipArchive<R>, name: &str) -> Result<T, ModpackError> {
+    let mut entry = archive.by_name(name).map_err(ModpackError::Zip)?;
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes).map_err(ModpackError::Io)?;
+    serde_json::from_slice(&bytes).map_err(ModpackError::Json)
+}
+
+//extract every entry under "<prefix>/" of the archive into the game directory
+fn extract_overrides<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, prefix: &str, game_dir: &Path) -> Result<usize, ModpackError> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let mut count = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(ModpackError::Zip)?;
+        let Some(relative) = entry.name().strip_prefix(prefix.as_str()).map(str::to_string) else {
+            continue;
+        };
+        if relative.is_empty() || entry.is_dir() {
+            continue;
+        }
+        let path = game_dir.join(safe_path(&relative)?);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(ModpackError::Io)?;
+        }
+        let mut file = cache::create_file(&path).map_err(ModpackError::Io)?;
+        std::io::copy(&mut entry, &mut file).map_err(ModpackError::Io)?;
+        count += 1;
+    }
+    log!("INFO", prefix, "{} files extracted", count);
+    Ok(count)
+}
+
+//a local file, or an url downloaded to a temporary file
+pub async fn open_source(source: &str) -> Result<(std::fs::File, Option<tempfile::TempPath>), ModpackError> {
+    if !(source.starts_with("http://") || source.starts_with("https://")) {
+        return Ok((std::fs::File::open(source).map_err(ModpackError::Io)?, None));
+    }
+    let temp = tempfile::NamedTempFile::new().map_err(ModpackError::Io)?.into_temp_path();
+    crate::download::download_file(source, &temp, |_| false).await?;
+    Ok((std::fs::File::open(&temp).map_err(ModpackError::Io)?, Some(temp)))
+}
+
+//a Modrinth .mrpack or a CurseForge zip, told apart by their index file
+pub async fn install_pack(source: &str, game_dir: &Path, curseforge: &CurseForgeConfig) -> Result<PackInfo, ModpackError> {
+    let (pack, _temp) = open_source(source).await?;
+    let mut archive = zip::ZipArchive::new(pack).map_err(ModpackError::Zip)?;
+    if archive.by_name("modrinth.index.json").is_ok() {
+        install_mrpack(&mut archive, game_dir).await
+    } else if archive.by_name("manifest.json").is_ok() {
+        install_curseforge(&mut archive, game_dir, curseforge).await
+    } else {
+        Err(ModpackError::Invalid(String::from("neither modrinth.index.json nor manifest.json found")))
+    }
+}
+
+//install a pack into an instance, created when it doesn't exist yet, and record what the pack needs
+pub async fn install(source: &str, instance_name: &str) -> Result<(Instance, PackInfo), ModpackError> {
+    let mut instance = match Instance::load(instance_name) {
+        Ok(instance) => instance,
+        Err(InstanceError::NotFound(_)) => Instance::create(instance_name, InstanceConfig::default())?,
+        Err(e) => return Err(e.into()),
+    };
+    let info = install_pack(source, &instance.game_dir, &env::CONFIG.curseforge).await?;
+    log!("OK", info.name, "installed into {}", instance.name);
+    if let Some(minecraft) = &info.minecraft {
+        instance.config.version = Some(minecraft.clone());
+    }
+    instance.config.loader = info.loader.clone();
+    instance.save()?;
+    Ok((instance, info))
+}
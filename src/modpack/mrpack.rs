@@ -0,0 +1,206 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+use std::path::Path;
+use futures_util::StreamExt;
+use crate::download::{File, HashAlgorithm};
+use crate::instance::{Loader, LoaderKind};
+use crate::{env, log};
+use super::{extract_overrides, read_json, safe_path, ModpackError, PackInfo};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvSupport {
+    Required,
+    Optional,
+    Unsupported,
+}
+
+//only the client side matters to a launcher, "server" is ignored
+#[derive(Deserialize, Debug)]
+pub struct MrpackEnv {
+    pub client: EnvSupport,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MrpackFile {
+    pub path: String,
+    pub hashes: HashMap<String, String>,
+    pub env: Option<MrpackEnv>,
+    //mirrors, tried in order
+    pub downloads: Vec<String>,
+    pub file_size: u64,
+}
+
+//modrinth.index.json at the root of a .mrpack
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MrpackIndex {
+    pub format_version: u32,
+    pub game: String,
+    pub version_id: String,
+    pub name: String,
+    pub files: Vec<MrpackFile>,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+}
+
+impl MrpackFile {
+    fn for_client(&self) -> bool {
+        self.env.as_ref().is_none_or(|env| env.client != EnvSupport::Unsupported)
+    }
+}
+
+impl MrpackIndex {
+    pub fn loader(&self) -> Option<Loader> {
+        [
+            ("fabric-loader", LoaderKind::Fabric),
+            ("quilt-loader", LoaderKind::Quilt),
+            ("forge", LoaderKind::Forge),
+            ("neoforge", LoaderKind::NeoForge),
+        ]
+        .into_iter()
+        .find_map(|(key, kind)| self.dependencies.get(key).map(|version| Loader { kind, version: version.clone() }))
+    }
+}
+
+//one file of the download engine, its downloads being mirrors of each other checked against the sha512
+async fn install_file(file: &MrpackFile, game_dir: &Path) -> Result<u64, ModpackError> {
+    safe_path(&file.path)?;
+    let sha512 = file.hashes.get("sha512").ok_or_else(|| ModpackError::Invalid(format!("no sha512 for {}", file.path)))?;
+    //ensure tries the mirrors before the url, the pack's order is kept with the last download as the url
+    let (url, mirrors) = file.downloads.split_last().ok_or_else(|| ModpackError::Invalid(format!("no download for {}", file.path)))?;
+    let download = File {
+        url: url.clone(),
+        mirrors: mirrors.to_vec(),
+        size: file.file_size,
+        hash: sha512.clone(),
+        algorithm: HashAlgorithm::Sha512,
+        path: file.path.clone(),
+        ..Default::default()
+    };
+    download.ensure(&game_dir.to_string_lossy()).await?;
+    Ok(file.file_size)
+}
+
+//download the files of a .mrpack into the game directory, then apply its overrides
+pub async fn install_mrpack<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, game_dir: &Path) -> Result<PackInfo, ModpackError> {
+    let index: MrpackIndex = read_json(archive, "modrinth.index.json")?;
+    if index.game != "minecraft" || index.format_version != 1 {
+        return Err(ModpackError::Invalid(format!("{} format {}", index.game, index.format_version)));
+    }
+    log!("INFO", index.name, "installing {} into {}", index.version_id, game_dir.display());
+
+    let files = index.files.iter().filter(|f| f.for_client()).collect::<Vec<_>>();
+    let mut stream = futures_util::stream::iter(files.into_iter().map(|file| install_file(file, game_dir)))
+        .buffer_unordered(env::DOWNLOAD_CONCURRENCY);
+    while let Some(result) = stream.next().await {
+        result?;
+    }
+    drop(stream);
+
+    extract_overrides(archive, "overrides", game_dir)?;
+    extract_overrides(archive, "client-overrides", game_dir)?;
+    Ok(PackInfo {
+        name: index.name.clone(),
+        minecraft: index.dependencies.get("minecraft").cloned(),
+        loader: index.loader(),
+        manual_downloads: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CurseForgeConfig;
+    use crate::modpack::install_pack;
+    use crate::test_server::{zip_fixture, TestServer};
+    use sha1::{Digest, Sha1};
+    use sha2::Sha512;
+
+    fn hashes(bytes: &[u8]) -> serde_json::Value {
+        serde_json::json!({
+            "sha1": hex::encode(Sha1::digest(bytes)),
+            "sha512": hex::encode(Sha512::digest(bytes)),
+        })
+    }
+
+    fn pack(files: serde_json::Value) -> Vec<u8> {
+        let index = serde_json::json!({
+            "formatVersion": 1,
+            "game": "minecraft",
+            "versionId": "1.0.0",
+            "name": "Fixture",
+            "files": files,
+            "dependencies": { "minecraft": "1.20.1", "fabric-loader": "0.15.7" },
+        });
+        zip_fixture(&[
+            ("modrinth.index.json", index.to_string().as_bytes()),
+            ("overrides/config/a.txt", b"common"),
+            ("overrides/options.txt", b"common"),
+            ("client-overrides/options.txt", b"client"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn installs_client_files_and_overrides() {
+        let server = TestServer::start(vec![
+            ("/mods/a.jar", b"mod a".to_vec()),
+            ("/mods/server.jar", b"server only".to_vec()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.mrpack");
+        std::fs::write(&source, pack(serde_json::json!([
+            { "path": "mods/a.jar", "hashes": hashes(b"mod a"), "downloads": [server.url("/mods/a.jar")], "fileSize": 5,
+              "env": { "client": "required", "server": "required" } },
+            { "path": "mods/server.jar", "hashes": hashes(b"server only"), "downloads": [server.url("/mods/server.jar")], "fileSize": 11,
+              "env": { "client": "unsupported", "server": "required" } },
+        ]))).unwrap();
+        let game_dir = dir.path().join("game");
+
+        let info = install_pack(&source.to_string_lossy(), &game_dir, &CurseForgeConfig::default()).await.ok().unwrap();
+
+        assert_eq!(std::fs::read(game_dir.join("mods/a.jar")).unwrap(), b"mod a");
+        assert!(!game_dir.join("mods/server.jar").exists());
+        assert_eq!(std::fs::read(game_dir.join("config/a.txt")).unwrap(), b"common");
+        assert_eq!(std::fs::read(game_dir.join("options.txt")).unwrap(), b"client");
+        assert_eq!(info.minecraft.as_deref(), Some("1.20.1"));
+        let loader = info.loader.unwrap();
+        assert_eq!((loader.kind, loader.version.as_str()), (LoaderKind::Fabric, "0.15.7"));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_next_mirror() {
+        let server = TestServer::start(vec![
+            ("/bad/a.jar", b"corrupted".to_vec()),
+            ("/good/a.jar", b"mod a".to_vec()),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.mrpack");
+        std::fs::write(&source, pack(serde_json::json!([
+            { "path": "mods/a.jar", "hashes": hashes(b"mod a"), "fileSize": 5,
+              "downloads": [server.url("/missing/a.jar"), server.url("/bad/a.jar"), server.url("/good/a.jar")] },
+        ]))).unwrap();
+        let game_dir = dir.path().join("game");
+
+        assert!(install_pack(&source.to_string_lossy(), &game_dir, &CurseForgeConfig::default()).await.is_ok());
+        assert_eq!(std::fs::read(game_dir.join("mods/a.jar")).unwrap(), b"mod a");
+        assert_eq!((server.requests("/missing/").len(), server.requests("/bad/").len()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn refuses_paths_outside_the_game_directory() {
+        let server = TestServer::start(vec![("/evil.jar", b"evil".to_vec())]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("fixture.mrpack");
+        std::fs::write(&source, pack(serde_json::json!([
+            { "path": "../evil.jar", "hashes": hashes(b"evil"), "downloads": [server.url("/evil.jar")], "fileSize": 4 },
+        ]))).unwrap();
+
+        let result = install_pack(&source.to_string_lossy(), &dir.path().join("game"), &CurseForgeConfig::default()).await;
+
+        assert!(matches!(result, Err(ModpackError::UnsafePath(_))));
+        assert!(!dir.path().join("evil.jar").exists());
+    }
+}
@@ -0,0 +1,396 @@
+use core::fmt;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use crate::config::{Config, HttpConfig, MirrorConfig};
+use crate::download::DownloadError;
+use crate::log;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OfflineMode {
+    //offline when the connectivity probe fails
+    Auto,
+    Always,
+    Never,
+}
+
+pub enum NetworkError {
+    Io(String, std::io::Error),
+    Reqwest(reqwest::Error),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Io(path, e) => write!(f, "IO error on {}: {}", path, e),
+            NetworkError::Reqwest(e) => write!(f, "Reqwest error: {}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for NetworkError {
+    fn from(e: reqwest::Error) -> Self {
+        NetworkError::Reqwest(e)
+    }
+}
+
+//the client every request goes through, with its settings
+struct Http {
+    client: reqwest::Client,
+    //same settings, asking for gzip or brotli: json compresses well, and is never fetched in ranges
+    json_client: reqwest::Client,
+    read_timeout: Duration,
+    mirrors: Vec<MirrorConfig>,
+    max_connections_per_host: usize,
+}
+
+#[cfg(not(test))]
+static OFFLINE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+static HTTP: OnceLock<Http> = OnceLock::new();
+//hosts that failed once during this session, tried after the others
+static UNHEALTHY: Mutex<Vec<String>> = Mutex::new(Vec::new());
+//bytes/s shared by every transfer, 0 for unlimited. The window and `bandwidth` change it while downloading
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+//when the transfers may go on under the limit
+static NEXT_SLOT: Mutex<Option<Instant>> = Mutex::new(None);
+static LAST_FOREGROUND: Mutex<Option<Instant>> = Mutex::new(None);
+static CONNECTIONS: Mutex<Vec<(String, Arc<Semaphore>)>> = Mutex::new(Vec::new());
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+//background transfers wait until the foreground ones have been quiet for this long
+const FOREGROUND_GRACE: Duration = Duration::from_secs(1);
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+tokio::task_local! {
+    static BACKGROUND: ();
+}
+
+//when set, nothing is downloaded: files are only checked locally and the missing ones reported
+#[cfg(not(test))]
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+#[cfg(not(test))]
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+//tests run side by side: one of them going offline leaves the others online
+#[cfg(test)]
+thread_local! {
+    static OFFLINE: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+#[cfg(test)]
+pub fn is_offline() -> bool {
+    OFFLINE.with(|offline| offline.get())
+}
+
+#[cfg(test)]
+pub fn set_offline(offline: bool) {
+    OFFLINE.with(|cell| cell.set(offline));
+}
+
+fn build(config: &HttpConfig, compressed: bool) -> Result<reqwest::Client, NetworkError> {
+    let mut builder = reqwest::Client::builder()
+        .gzip(compressed)
+        .brotli(compressed)
+        .user_agent(&config.user_agent)
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs));
+    //without one, reqwest still honours the HTTP(S)_PROXY environment variables
+    if let Some(url) = &config.proxy {
+        let mut proxy = reqwest::Proxy::all(url)?.no_proxy(reqwest::NoProxy::from_string(&config.no_proxy.join(",")));
+        if let Some(username) = &config.proxy_username {
+            proxy = proxy.basic_auth(username, config.proxy_password.as_deref().unwrap_or(""));
+        }
+        builder = builder.proxy(proxy);
+    }
+    for path in &config.ca_certificates {
+        let pem = std::fs::read(path).map_err(|e| NetworkError::Io(path.clone(), e))?;
+        //a bundle holds several certificates, reqwest parses them one at a time
+        let text = String::from_utf8_lossy(&pem);
+        for certificate in text.split_inclusive("-----END CERTIFICATE-----").filter(|c| c.contains("-----BEGIN CERTIFICATE-----")) {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(certificate.trim().as_bytes())?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+//set once at startup from launcher.json, before anything is downloaded
+pub fn init(config: &Config) -> Result<(), NetworkError> {
+    let http = Http {
+        client: build(&config.http, false)?,
+        json_client: build(&config.http, true)?,
+        read_timeout: Duration::from_secs(config.http.read_timeout_secs),
+        mirrors: config.mirrors.clone(),
+        max_connections_per_host: config.http.max_connections_per_host,
+    };
+    set_bandwidth_limit(config.http.bandwidth_limit);
+    let _ = HTTP.set(http);
+    Ok(())
+}
+
+fn http() -> &'static Http {
+    HTTP.get_or_init(|| Http {
+        client: build(&HttpConfig::default(), false).unwrap_or_default(),
+        json_client: build(&HttpConfig::default(), true).unwrap_or_default(),
+        read_timeout: Duration::from_secs(HttpConfig::default().read_timeout_secs),
+        mirrors: Vec::new(),
+        max_connections_per_host: 0,
+    })
+}
+
+pub fn client() -> &'static reqwest::Client {
+    &http().client
+}
+
+pub async fn get(url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    client().get(url).send().await
+}
+
+//for manifests and indexes, decompressed on the fly when the server compresses them
+pub async fn get_json(url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    http().json_client.get(url).send().await
+}
+
+pub fn bandwidth_limit() -> u64 {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+pub fn set_bandwidth_limit(bytes_per_second: u64) {
+    LIMIT.store(bytes_per_second, Ordering::Relaxed);
+}
+
+//"unlimited", or bytes/s with an optional K, M or G suffix: "500K", "2M"
+pub fn parse_rate(rate: &str) -> Option<u64> {
+    let rate = rate.trim();
+    if rate.eq_ignore_ascii_case("unlimited") {
+        return Some(0);
+    }
+    let (number, unit) = match rate.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&rate[..i], c.to_ascii_uppercase()),
+        _ => (rate, ' '),
+    };
+    let factor = match unit {
+        ' ' => 1,
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        _ => return None,
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(factor)
+}
+
+//the limit written by `bandwidth` after startup is applied to the transfers in progress
+pub async fn watch_bandwidth_limit(path: &Path) {
+    let mut applied = SystemTime::now();
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        let Some(modified) = std::fs::metadata(path).and_then(|m| m.modified()).ok().filter(|m| *m > applied) else {
+            continue;
+        };
+        applied = modified;
+        match std::fs::read_to_string(path).ok().as_deref().and_then(parse_rate) {
+            Some(limit) => {
+                log!("INFO", "bandwidth limit set to {} bytes/s", limit);
+                set_bandwidth_limit(limit);
+            }
+            None => log!("WARN", path.display(), "invalid bandwidth limit"),
+        }
+    }
+}
+
+//run the transfers of a future after the foreground ones, e.g. pre-fetching the next update
+pub async fn background<F: Future>(future: F) -> F::Output {
+    BACKGROUND.scope((), future).await
+}
+
+async fn schedule(bytes: usize) {
+    if BACKGROUND.try_with(|_| ()).is_ok() {
+        loop {
+            let last = *LAST_FOREGROUND.lock().expect("foreground lock");
+            match last.map(|l| l.elapsed()) {
+                Some(quiet) if quiet < FOREGROUND_GRACE => tokio::time::sleep(FOREGROUND_GRACE - quiet).await,
+                _ => break,
+            }
+        }
+    } else {
+        *LAST_FOREGROUND.lock().expect("foreground lock") = Some(Instant::now());
+    }
+    let limit = bandwidth_limit();
+    if limit == 0 {
+        return;
+    }
+    //every chunk books the time it takes at the limit, after the ones already booked
+    let until = {
+        let mut next = NEXT_SLOT.lock().expect("bandwidth lock");
+        let start = next.map_or_else(Instant::now, |n| n.max(Instant::now()));
+        let until = start + Duration::from_secs_f64(bytes as f64 / limit as f64);
+        *next = Some(until);
+        until
+    };
+    tokio::time::sleep_until(until).await;
+}
+
+//held for a whole transfer when max_connections_per_host is set
+pub async fn connection(url: &str) -> Option<OwnedSemaphorePermit> {
+    let max = http().max_connections_per_host;
+    if max == 0 {
+        return None;
+    }
+    let host = host(url);
+    let semaphore = {
+        let mut connections = CONNECTIONS.lock().expect("connections lock");
+        match connections.iter().find(|(h, _)| *h == host) {
+            Some((_, semaphore)) => semaphore.clone(),
+            None => {
+                let semaphore = Arc::new(Semaphore::new(max));
+                connections.push((host, semaphore.clone()));
+                semaphore
+            }
+        }
+    };
+    semaphore.acquire_owned().await.ok()
+}
+
+//the next chunk of a body, an error once the server stayed silent for the read timeout.
+//Paced by the bandwidth limit and the priority of the transfer
+pub async fn next_chunk<S, T>(stream: &mut S) -> Option<Result<T, DownloadError>>
+where
+    S: Stream<Item = reqwest::Result<T>> + Unpin,
+    T: AsRef<[u8]>,
+{
+    let read_timeout = http().read_timeout;
+    let chunk = match tokio::time::timeout(read_timeout, stream.next()).await {
+        Ok(chunk) => chunk.map(|c| c.map_err(DownloadError::Reqwest)),
+        Err(_) => Some(Err(DownloadError::Timeout(read_timeout))),
+    };
+    if let Some(Ok(bytes)) = &chunk {
+        schedule(bytes.as_ref().len()).await;
+    }
+    chunk
+}
+
+//the whole body, read under the same timeout as streamed downloads
+pub async fn body(resp: reqwest::Response) -> Result<Vec<u8>, DownloadError> {
+    let mut bytes = Vec::with_capacity(resp.content_length().unwrap_or(0) as usize);
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = next_chunk(&mut stream).await {
+        bytes.extend_from_slice(&chunk?);
+    }
+    Ok(bytes)
+}
+
+fn host(url: &str) -> String {
+    reqwest::Url::parse(url).map_or_else(|_| url.to_string(), |u| u.origin().ascii_serialization())
+}
+
+//a host that failed (unreachable or bad status) goes to the end of the list for the session. A file that doesn't
+//match its hash says nothing of the host: it may only hold another version
+pub fn mark_unhealthy(url: &str) {
+    let host = host(url);
+    let mut unhealthy = UNHEALTHY.lock().expect("unhealthy hosts lock");
+    if !unhealthy.contains(&host) {
+        log!("WARN", host, "failed, tried last from now on");
+        unhealthy.push(host);
+    }
+}
+
+fn expand(mirrors: &[MirrorConfig], urls: &[String]) -> Vec<String> {
+    let mut candidates = Vec::new();
+    for url in urls.iter().filter(|u| !u.is_empty()) {
+        for mirror in mirrors {
+            let origin = mirror.origin.trim_end_matches('/');
+            let Some(rest) = url.strip_prefix(origin).filter(|r| r.is_empty() || r.starts_with('/')) else {
+                continue;
+            };
+            candidates.extend(mirror.urls.iter().map(|m| format!("{}{}", m.trim_end_matches('/'), rest)));
+        }
+        candidates.push(url.clone());
+    }
+    let mut seen = Vec::new();
+    candidates.retain(|c| if seen.contains(c) { false } else { seen.push(c.clone()); true });
+    candidates
+}
+
+//every url worth trying for a file known by the given ones: the configured mirrors before each of them,
+//the hosts that already failed last
+pub fn candidates(urls: &[String]) -> Vec<String> {
+    let candidates = expand(&http().mirrors, urls);
+    let unhealthy = UNHEALTHY.lock().expect("unhealthy hosts lock");
+    let (healthy, failed): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|c| !unhealthy.contains(&host(c)));
+    healthy.into_iter().chain(failed).collect()
+}
+
+//a quick HEAD on the given url, any answer counts as being online
+pub async fn probe(url: &str) -> bool {
+    match client().head(url).timeout(PROBE_TIMEOUT).send().await {
+        Ok(_) => true,
+        Err(e) => {
+            log!("WARN", url, "unreachable: {}", e);
+            false
+        }
+    }
+}
+
+pub async fn resolve_offline(mode: OfflineMode, forced: bool, probe_url: &str) -> bool {
+    forced || match mode {
+        OfflineMode::Always => true,
+        OfflineMode::Never => false,
+        OfflineMode::Auto => !probe(probe_url).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_client_from_the_http_settings() {
+        let config = HttpConfig {
+            proxy: Some(String::from("http://proxy.school.lan:3128")),
+            proxy_username: Some(String::from("student")),
+            proxy_password: Some(String::from("secret")),
+            no_proxy: vec![String::from(".lan")],
+            ..Default::default()
+        };
+        assert!(build(&config, true).is_ok());
+
+        let config = HttpConfig { ca_certificates: vec![String::from("missing-root-ca.pem")], ..Default::default() };
+        assert!(matches!(build(&config, false), Err(NetworkError::Io(path, _)) if path == "missing-root-ca.pem"));
+    }
+
+    #[test]
+    fn parses_bandwidth_rates() {
+        assert_eq!(parse_rate("unlimited"), Some(0));
+        assert_eq!(parse_rate("1500"), Some(1500));
+        assert_eq!(parse_rate("500K"), Some(500 * 1024));
+        assert_eq!(parse_rate("2m"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_rate("fast"), None);
+        assert_eq!(parse_rate("99999999999G"), None);
+    }
+
+    #[test]
+    fn tries_the_mirrors_before_the_origin() {
+        let mirrors = vec![MirrorConfig {
+            origin: String::from("https://resources.download.minecraft.net/"),
+            urls: vec![String::from("http://192.168.1.10/resources")],
+        }];
+        let urls = vec![
+            String::from("https://resources.download.minecraft.net/ab/abcdef"),
+            String::from("https://resources.download.minecraft.network/ab/abcdef"),
+        ];
+        assert_eq!(expand(&mirrors, &urls), [
+            "http://192.168.1.10/resources/ab/abcdef",
+            "https://resources.download.minecraft.net/ab/abcdef",
+            "https://resources.download.minecraft.network/ab/abcdef",
+        ]);
+    }
+}
@@ -0,0 +1,205 @@
+use core::fmt;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use crate::cache::{self, Store};
+use crate::config::PeerConfig;
+use crate::download::file_to_hash;
+use crate::log;
+
+pub enum PeerError {
+    Io(std::io::Error),
+    CacheDisabled,
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerError::Io(e) => write!(f, "IO error: {}", e),
+            PeerError::CacheDisabled => write!(f, "The cache is disabled, there is nothing to serve"),
+        }
+    }
+}
+
+impl From<std::io::Error> for PeerError {
+    fn from(e: std::io::Error) -> Self {
+        PeerError::Io(e)
+    }
+}
+
+//broadcast by launchers looking for peers, answered with ANSWER and the http port
+const QUESTION: &[u8] = b"minecraft-portable-launcher peers?";
+const ANSWER: &str = "minecraft-portable-launcher peer ";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_REQUEST: usize = 8 * 1024;
+
+//base urls of the peers found at startup, asked before any mirror
+static PEERS: OnceLock<Vec<String>> = OnceLock::new();
+
+pub fn init(peers: Vec<String>) {
+    let _ = PEERS.set(peers);
+}
+
+//where the peers would serve the object with the given hash
+pub fn urls(hash: &str) -> Vec<String> {
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Vec::new();
+    }
+    PEERS.get().map_or_else(Vec::new, |peers| {
+        peers.iter().map(|p| format!("{}/objects/{}", p.trim_end_matches('/'), hash)).collect()
+    })
+}
+
+//the configured addresses, then whoever answers a broadcast on the local network
+pub async fn discover(config: &PeerConfig) -> Vec<String> {
+    let mut peers = config.addresses.clone();
+    if config.discover {
+        match broadcast(config.port).await {
+            Ok(found) => peers.extend(found),
+            Err(e) => log!("WARN", "peer discovery failed: {}", e),
+        }
+    }
+    let mut seen = HashSet::new();
+    peers.retain(|p| seen.insert(p.clone()));
+    if !peers.is_empty() {
+        log!("INFO", "peers: {}", peers.join(", "));
+    }
+    peers
+}
+
+async fn broadcast(port: u16) -> std::io::Result<Vec<String>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(QUESTION, ("255.255.255.255", port)).await?;
+    let mut found = Vec::new();
+    let mut buffer = [0u8; 128];
+    let deadline = tokio::time::Instant::now() + DISCOVERY_TIMEOUT;
+    while let Ok(Ok((n, from))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        let answer = String::from_utf8_lossy(&buffer[..n]);
+        if let Some(Ok(http_port)) = answer.strip_prefix(ANSWER).map(str::parse::<u16>) {
+            found.push(format!("http://{}", SocketAddr::new(from.ip(), http_port)));
+        }
+    }
+    Ok(found)
+}
+
+//the objects of the shared cache over http, GET /objects/<hash>, until the process is stopped
+pub async fn serve(port: u16) -> Result<(), PeerError> {
+    let store = cache::shared().ok_or(PeerError::CacheDisabled)?;
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    let discovery = UdpSocket::bind(("0.0.0.0", port)).await?;
+    log!("INFO", "serving the cache on port {}", port);
+    tokio::spawn(answer_discovery(discovery, port));
+    run(listener, store).await
+}
+
+async fn answer_discovery(socket: UdpSocket, http_port: u16) {
+    let mut buffer = [0u8; 128];
+    while let Ok((n, from)) = socket.recv_from(&mut buffer).await {
+        if &buffer[..n] == QUESTION {
+            let _ = socket.send_to(format!("{}{}", ANSWER, http_port).as_bytes(), from).await;
+        }
+    }
+}
+
+async fn run(listener: TcpListener, store: &'static Store) -> Result<(), PeerError> {
+    //objects checked once per run, a corrupted one is never served
+    let verified = Arc::new(Mutex::new(HashSet::new()));
+    loop {
+        let (socket, from) = listener.accept().await?;
+        let verified = verified.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(socket, store, &verified).await {
+                log!("WARN", from, "peer request failed: {}", e);
+            }
+        });
+    }
+}
+
+//the hash algorithm is told by the length of the hash
+fn matches_hash(path: &Path, hash: &str) -> bool {
+    let digest = match hash.len() {
+        32 => file_to_hash::<Md5, _>(path),
+        40 => file_to_hash::<Sha1, _>(path),
+        64 => file_to_hash::<Sha256, _>(path),
+        128 => file_to_hash::<Sha512, _>(path),
+        _ => return false,
+    };
+    digest.is_ok_and(|d| hex::encode(d) == hash)
+}
+
+async fn handle(mut socket: TcpStream, store: &Store, verified: &Mutex<HashSet<String>>) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 || request.len() > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let request = String::from_utf8_lossy(&request).into_owned();
+    let mut line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (line.next().unwrap_or_default(), line.next().unwrap_or_default());
+    let hash = path.strip_prefix("/objects/").unwrap_or_default().to_string();
+
+    let object = match store.lookup(&hash) {
+        Some(object) if verified.lock().expect("verified objects lock").contains(&hash) => Some(object),
+        Some(object) => {
+            let (checked, expected) = (object.clone(), hash.clone());
+            let valid = tokio::task::spawn_blocking(move || matches_hash(&checked, &expected)).await.unwrap_or(false);
+            if valid {
+                verified.lock().expect("verified objects lock").insert(hash.clone());
+            } else {
+                log!("WARN", hash, "corrupted object not served");
+            }
+            valid.then_some(object)
+        }
+        None => None,
+    };
+    let (Some(object), "GET" | "HEAD") = (object, method) else {
+        return socket.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+    };
+    let mut file = tokio::fs::File::open(&object).await?;
+    let length = file.metadata().await?.len();
+    socket.write_all(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", length).as_bytes()).await?;
+    if method == "GET" {
+        tokio::io::copy(&mut file, &mut socket).await?;
+    }
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1::Digest;
+    use crate::network;
+
+    #[tokio::test]
+    async fn serves_verified_objects_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: &'static Store = Box::leak(Box::new(Store::new(dir.path().join("cache"))));
+        let (good, bad) = (hex::encode(Sha1::digest(b"content")), hex::encode(Sha1::digest(b"other")));
+        let file = dir.path().join("x.jar");
+        std::fs::write(&file, b"content").unwrap();
+        store.insert(&file, &good);
+        store.insert(&file, &bad);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(run(listener, store));
+
+        let resp = network::get(&format!("{}/objects/{}", base, good)).await.unwrap();
+        assert_eq!(network::body(resp.error_for_status().unwrap()).await.ok().unwrap(), b"content");
+        for hash in [bad, String::from("0123456789")] {
+            let resp = network::get(&format!("{}/objects/{}", base, hash)).await.unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+        }
+    }
+}
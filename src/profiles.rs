@@ -0,0 +1,200 @@
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::time::SystemTime;
+use core::fmt;
+use crate::instance::Instance;
+use crate::java::{machine_heap, memory_arguments};
+use crate::version::{substitute, JavaVersion, Variables, Version, VersionManifest};
+use crate::{env, log};
+
+//profiles written by the portable launcher, any other profile belongs to the user
+const PROFILE_PREFIX: &str = "portable-";
+const DEFAULT_ICON: &str = "Grass";
+
+pub enum ProfilesError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ProfilesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfilesError::Io(e) => write!(f, "IO error: {}", e),
+            ProfilesError::Json(e) => write!(f, "JSON error: {}", e),
+        }
+    }
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+fn read_profiles(path: &Path) -> Result<Value, ProfilesError> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(ProfilesError::Json),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(json!({ "version": 3 })),
+        Err(e) => Err(ProfilesError::Io(e)),
+    }
+}
+
+//create or merge launcher_profiles.json: one profile per instance, the selected one marked as last used.
+//accounts, settings and the user's own profiles are left untouched
+pub fn write_profiles<F>(path: &Path, instances: &[Instance], selected: &str, java_args: F) -> Result<(), ProfilesError>
+where
+    F: Fn(&Instance) -> String
+{
+    let mut root = read_profiles(path)?;
+    let Some(root_map) = root.as_object_mut() else {
+        return Err(ProfilesError::Json(serde::de::Error::custom("launcher_profiles.json is not an object")));
+    };
+    let profiles = root_map.entry("profiles").or_insert_with(|| Value::Object(Map::new()));
+    if !profiles.is_object() {
+        *profiles = Value::Object(Map::new());
+    }
+    let profiles = profiles.as_object_mut().expect("profiles is an object");
+
+    profiles.retain(|key, _| {
+        let keep = !key.starts_with(PROFILE_PREFIX) || instances.iter().any(|i| key[PROFILE_PREFIX.len()..] == i.name);
+        if !keep {
+            log!("INFO", key, "stale profile removed");
+        }
+        keep
+    });
+
+    let now = now();
+    for instance in instances {
+        let game_dir = std::env::current_dir()
+            .map(|dir| dir.join(&instance.game_dir))
+            .map_err(ProfilesError::Io)?;
+        let profile = profiles
+            .entry(format!("{}{}", PROFILE_PREFIX, instance.name))
+            .or_insert_with(|| json!({ "created": now }));
+        if !profile.is_object() {
+            *profile = json!({ "created": now });
+        }
+        let profile = profile.as_object_mut().expect("profile is an object");
+        profile.insert("name".into(), json!(instance.name));
+        profile.insert("type".into(), json!("custom"));
+        profile.insert("gameDir".into(), json!(game_dir.to_string_lossy()));
+        profile.insert("javaArgs".into(), json!(java_args(instance)));
+        profile.insert("lastVersionId".into(), json!(instance.version_id()));
+        profile.insert("icon".into(), json!(instance.config.icon.as_deref().unwrap_or(DEFAULT_ICON)));
+        if instance.name == selected {
+            profile.insert("lastUsed".into(), json!(now));
+        } else if !profile.contains_key("lastUsed") {
+            profile.insert("lastUsed".into(), json!("1970-01-01T00:00:00.000Z"));
+        }
+    }
+
+    let bytes = serde_json::to_vec_pretty(&root).map_err(ProfilesError::Json)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(ProfilesError::Io)?;
+    }
+    //written next to the original then renamed, a crash never leaves a truncated file
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, bytes).map_err(ProfilesError::Io)?;
+    std::fs::rename(&temp, path).map_err(ProfilesError::Io)
+}
+
+//java the official launcher runs a version with, from its json in versions_dir. Loader profiles take it
+//from the version they inherit from. None when the json isn't there yet
+fn java_major(versions_dir: &Path, id: &str) -> Option<u32> {
+    let version = Version::from_path(versions_dir.join(id).join(format!("{}.json", id))).ok()?;
+    match (version.java_version, version.inherits_from) {
+        (Some(java), _) => Some(java.major_version),
+        (None, Some(parent)) => java_major(versions_dir, &parent),
+        (None, None) => Some(JavaVersion::default().major_version),
+    }
+}
+
+//javaArgs is split on spaces, values holding one are quoted
+fn quote(argument: &str) -> String {
+    if argument.contains(char::is_whitespace) {
+        format!("\"{}\"", argument.replace('"', "\\\""))
+    } else {
+        argument.to_string()
+    }
+}
+
+//profiles of every instance for the official launcher in the shared work directory. Version aliases are
+//resolved through the manifest when there is one, the loaders' ids are made from the real version
+pub fn update_profiles(selected: &Instance, manifest: Option<&VersionManifest>) -> Result<(), ProfilesError> {
+    let mut instances = Instance::list().unwrap_or_default();
+    instances.insert(0, Instance::implicit());
+    if let Some(manifest) = manifest {
+        for instance in &mut instances {
+            instance.config.version = Some(manifest.resolve(instance.version()).to_string());
+        }
+    }
+    let mut variables = Variables::new();
+    variables.extend(machine_heap(&env::CONFIG.memory).variables());
+    let versions_dir = Path::new(env::WORK_DIR).join("versions");
+    let path = Path::new(env::WORK_DIR).join("launcher_profiles.json");
+    write_profiles(&path, &instances, &selected.name, |instance| {
+        let major = java_major(&versions_dir, &instance.version_id());
+        if major.is_none() {
+            log!("INFO", instance.name, "java version unknown, no gc flags in its profile");
+        }
+        let mut arguments = memory_arguments(&env::CONFIG.memory, major, &variables);
+        arguments.extend(instance.config.jvm_args.iter().map(|a| substitute(a, &variables)));
+        arguments.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::InstanceConfig;
+
+    #[test]
+    fn merges_into_the_user_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("launcher_profiles.json");
+        std::fs::write(&path, serde_json::to_vec(&json!({
+            "profiles": {
+                "mine": { "name": "My profile", "type": "custom", "lastVersionId": "1.8.9" },
+                "portable-deleted": { "name": "deleted" },
+                "portable-pvp": { "name": "pvp", "created": "2020-01-01T00:00:00.000Z", "resolution": { "width": 854 } },
+            },
+            "settings": { "crashAssistance": true },
+            "clientToken": "token",
+            "version": 3,
+        })).unwrap()).unwrap();
+        let instance = |name: &str| Instance {
+            name: name.to_string(),
+            game_dir: Path::new("instances").join(name).join("minecraft"),
+            config: InstanceConfig { version: Some(String::from("1.20.1")), ..Default::default() },
+        };
+
+        write_profiles(&path, &[instance("pvp"), instance("new")], "new", |_| String::from("-Xmx2G")).ok().unwrap();
+
+        let root: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(root["settings"], json!({ "crashAssistance": true }));
+        assert_eq!(root["clientToken"], "token");
+        let profiles = root["profiles"].as_object().unwrap();
+        assert_eq!(profiles.keys().collect::<Vec<_>>(), ["mine", "portable-pvp", "portable-new"]);
+        assert_eq!(profiles["mine"]["lastVersionId"], "1.8.9");
+        assert_eq!(profiles["portable-pvp"]["created"], "2020-01-01T00:00:00.000Z");
+        assert_eq!(profiles["portable-pvp"]["resolution"], json!({ "width": 854 }));
+        assert_eq!((&profiles["portable-new"]["javaArgs"], &profiles["portable-new"]["lastVersionId"]), (&json!("-Xmx2G"), &json!("1.20.1")));
+        assert_eq!(profiles["portable-pvp"]["lastUsed"], "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn reads_the_java_of_the_version() {
+        let dir = tempfile::tempdir().unwrap();
+        for (id, json) in [
+            ("1.12.2", json!({ "id": "1.12.2", "mainClass": "m" })),
+            ("1.20.1", json!({ "id": "1.20.1", "mainClass": "m", "javaVersion": { "component": "java-runtime-gamma", "majorVersion": 17 } })),
+            ("fabric-loader-0.15.0-1.20.1", json!({ "id": "fabric-loader-0.15.0-1.20.1", "inheritsFrom": "1.20.1", "mainClass": "m" })),
+        ] {
+            std::fs::create_dir_all(dir.path().join(id)).unwrap();
+            std::fs::write(dir.path().join(id).join(format!("{}.json", id)), json.to_string()).unwrap();
+        }
+
+        assert_eq!(java_major(dir.path(), "1.12.2"), Some(8));
+        assert_eq!(java_major(dir.path(), "fabric-loader-0.15.0-1.20.1"), Some(17));
+        assert_eq!(java_major(dir.path(), "1.21"), None);
+        assert_eq!(quote("-Dname=a b"), "\"-Dname=a b\"");
+    }
+}
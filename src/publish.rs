@@ -0,0 +1,197 @@
+use core::fmt;
+use std::path::{Path, PathBuf};
+use crate::download::{Directory, DownloadError, File, Files, HashAlgorithm, Link};
+
+pub enum PublishError {
+    Io(std::io::Error),
+    Download(DownloadError),
+    InvalidGlob(String),
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::Io(e) => write!(f, "IO error: {}", e),
+            PublishError::Download(e) => write!(f, "{}", e),
+            PublishError::InvalidGlob(glob) => write!(f, "Invalid glob: {}", glob),
+        }
+    }
+}
+
+impl From<std::io::Error> for PublishError {
+    fn from(e: std::io::Error) -> Self {
+        PublishError::Io(e)
+    }
+}
+
+impl From<DownloadError> for PublishError {
+    fn from(e: DownloadError) -> Self {
+        PublishError::Download(e)
+    }
+}
+
+//what `manifest generate` puts in the manifest
+#[derive(Default)]
+pub struct GenerateOptions {
+    pub base_url: String,
+    //every file when empty
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub version: Option<u64>,
+    pub changelog: Option<String>,
+    pub algorithm: HashAlgorithm,
+}
+
+//globs match the path relative to the directory, "/" separated. A glob without "/" also matches the file name
+//anywhere: "*.log" skips every log, "saves/**" one directory
+struct Globs(Vec<(glob::Pattern, bool)>);
+
+impl Globs {
+    fn new(globs: &[String]) -> Result<Globs, PublishError> {
+        globs.iter()
+            .map(|g| glob::Pattern::new(g).map(|p| (p, !g.contains('/'))).map_err(|_| PublishError::InvalidGlob(g.clone())))
+            .collect::<Result<_, _>>()
+            .map(Globs)
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.0.iter().any(|(glob, by_name)| glob.matches(path) || (*by_name && glob.matches(name)))
+    }
+}
+
+//unreserved characters are kept, anything else in a path segment is percent-encoded
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+enum Entry {
+    File(PathBuf),
+    EmptyDirectory(PathBuf),
+    Link(PathBuf),
+}
+
+impl Entry {
+    fn path(&self) -> &Path {
+        match self {
+            Entry::File(path) | Entry::EmptyDirectory(path) | Entry::Link(path) => path,
+        }
+    }
+}
+
+//links are recorded as links, never followed
+fn walk(dir: &Path, entries: &mut Vec<Entry>) -> std::io::Result<()> {
+    let mut children = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        let file_type = child.file_type()?;
+        if file_type.is_symlink() {
+            entries.push(Entry::Link(child.path()));
+        } else if file_type.is_dir() {
+            if std::fs::read_dir(child.path())?.next().is_none() {
+                entries.push(Entry::EmptyDirectory(child.path()));
+            }
+            walk(&child.path(), entries)?;
+        } else if file_type.is_file() {
+            entries.push(Entry::File(child.path()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(path.metadata()?.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> std::io::Result<bool> {
+    Ok(false)
+}
+
+//the manifest of dir as served from base_url, readable by Files as is
+pub fn generate(dir: &Path, options: &GenerateOptions) -> Result<Files, PublishError> {
+    let (include, exclude) = (Globs::new(&options.include)?, Globs::new(&options.exclude)?);
+    let mut entries = Vec::new();
+    walk(dir, &mut entries)?;
+    let mut manifest = Files { version: options.version, changelog: options.changelog.clone(), ..Default::default() };
+    for entry in entries {
+        let path = entry.path();
+        let relative = path.strip_prefix(dir).expect("walked from dir")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if (!options.include.is_empty() && !include.matches(&relative)) || exclude.matches(&relative) {
+            continue;
+        }
+        match entry {
+            Entry::File(_) => manifest.files.push(File {
+                url: format!("{}/{}", options.base_url.trim_end_matches('/'), encode_path(&relative)),
+                size: path.metadata()?.len(),
+                hash: options.algorithm.file_hash(path)?,
+                path: relative,
+                algorithm: options.algorithm,
+                executable: is_executable(path)?,
+                ..Default::default()
+            }),
+            Entry::EmptyDirectory(_) => manifest.directories.push(Directory { path: relative, ..Default::default() }),
+            Entry::Link(_) => manifest.links.push(Link {
+                path: relative,
+                target: std::fs::read_link(path)?.to_string_lossy().replace('\\', "/"),
+                ..Default::default()
+            }),
+        }
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_a_manifest_files_can_read() {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in [("mods/a b.jar", "a"), ("config/x.toml", "x"), ("logs/latest.log", "log"), ("options.txt", "o")] {
+            std::fs::create_dir_all(dir.path().join(path).parent().unwrap()).unwrap();
+            std::fs::write(dir.path().join(path), content).unwrap();
+        }
+        let options = GenerateOptions {
+            base_url: String::from("https://example.com/pack/"),
+            include: vec![String::from("mods/**"), String::from("config/**"), String::from("*.log")],
+            exclude: vec![String::from("logs/**")],
+            version: Some(3),
+            ..Default::default()
+        };
+
+        let manifest = generate(dir.path(), &options).ok().unwrap();
+
+        let files: Files = serde_json::from_slice(&serde_json::to_vec(&manifest).unwrap()).unwrap();
+        assert_eq!(files.version, Some(3));
+        let paths = files.iter().map(|f| (f.path.as_str(), f.url.as_str())).collect::<Vec<_>>();
+        assert_eq!(paths, [
+            ("config/x.toml", "https://example.com/pack/config/x.toml"),
+            ("mods/a b.jar", "https://example.com/pack/mods/a%20b.jar"),
+        ]);
+        assert_eq!(files.iter().next().unwrap().hash, "11f6ad8ec52a2984abaafd7c3b516503785c2072");
+        #[cfg(unix)]
+        {
+            std::fs::create_dir(dir.path().join("mods/empty")).unwrap();
+            std::os::unix::fs::symlink("a b.jar", dir.path().join("mods/latest.jar")).unwrap();
+            let manifest = generate(dir.path(), &GenerateOptions { base_url: String::from("u"), ..Default::default() }).ok().unwrap();
+            assert_eq!(manifest.directories.iter().map(|d| d.path.as_str()).collect::<Vec<_>>(), ["mods/empty"]);
+            assert_eq!(manifest.links.iter().map(|l| (l.path.as_str(), l.target.as_str())).collect::<Vec<_>>(), [("mods/latest.jar", "a b.jar")]);
+        }
+        let plain: Files = serde_json::from_str(r#"[{"url": "u", "size": 1, "hash": "h", "path": "p"}]"#).unwrap();
+        assert_eq!((plain.version, plain.files.len()), (None, 1));
+    }
+}
@@ -0,0 +1,140 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use core::fmt;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::path::Path;
+use crate::{env, log};
+
+pub enum SignatureError {
+    Io(std::io::Error),
+    InvalidKey(String),
+    Unsigned(String),
+    BadSignature(String),
+    NoTrustedKey(String),
+    //a manifest older than the one already applied, (url, version, applied version)
+    Rollback(String, u64, u64),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Io(e) => write!(f, "IO error: {}", e),
+            SignatureError::InvalidKey(key) => write!(f, "Invalid ed25519 key: {}", key),
+            SignatureError::Unsigned(url) => write!(f, "{} has no signature", url),
+            SignatureError::BadSignature(url) => write!(f, "{} is not signed by a trusted key", url),
+            SignatureError::NoTrustedKey(url) => write!(f, "{} can't be verified, no key in manifest_keys (or set allow_unsigned_manifests)", url),
+            SignatureError::Rollback(url, version, applied) => write!(f, "{} is version {}, older than the applied version {}", url, version, applied),
+        }
+    }
+}
+
+impl From<std::io::Error> for SignatureError {
+    fn from(e: std::io::Error) -> Self {
+        SignatureError::Io(e)
+    }
+}
+
+//detached signature of the manifest at url, base64 like the keys
+pub fn signature_url(url: &str) -> String {
+    format!("{}.sig", url)
+}
+
+fn decode<const N: usize>(encoded: &str) -> Result<[u8; N], SignatureError> {
+    STANDARD.decode(encoded.trim()).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| SignatureError::InvalidKey(encoded.trim().to_string()))
+}
+
+//the keys compiled in (env::MANIFEST_KEYS) and the ones pinned in launcher.json
+pub fn trusted_keys(pinned: &[String]) -> Result<Vec<VerifyingKey>, SignatureError> {
+    env::MANIFEST_KEYS.iter().copied()
+        .chain(pinned.iter().map(String::as_str))
+        .map(|key| VerifyingKey::from_bytes(&decode(key)?).map_err(|_| SignatureError::InvalidKey(key.to_string())))
+        .collect()
+}
+
+//manifest is the exact bytes served, signature the content of the .sig file, None when there is none
+pub fn verify(url: &str, manifest: &[u8], signature: Option<&str>, keys: &[VerifyingKey]) -> Result<(), SignatureError> {
+    let signature = signature.ok_or_else(|| SignatureError::Unsigned(url.to_string()))?;
+    let signature = decode(signature).map(|s| Signature::from_bytes(&s)).map_err(|_| SignatureError::BadSignature(url.to_string()))?;
+    if keys.iter().any(|key| key.verify(manifest, &signature).is_ok()) {
+        Ok(())
+    } else {
+        Err(SignatureError::BadSignature(url.to_string()))
+    }
+}
+
+//the manifest policy: a bad signature is always refused, a missing one (or no key to check it against)
+//only with allow_unsigned
+pub fn check(url: &str, manifest: &[u8], signature: Option<&str>, keys: &[VerifyingKey], allow_unsigned: bool) -> Result<(), SignatureError> {
+    match (keys.is_empty(), signature, allow_unsigned) {
+        (true, _, false) => Err(SignatureError::NoTrustedKey(url.to_string())),
+        (true, _, true) | (false, None, true) => {
+            log!("WARN", url, "manifest not verified, allow_unsigned_manifests is set");
+            Ok(())
+        }
+        (false, signature, _) => verify(url, manifest, signature, keys),
+    }
+}
+
+//a signed manifest stays valid forever, an older one must not roll the instance back
+pub fn check_version(url: &str, version: Option<u64>, applied: Option<u64>) -> Result<(), SignatureError> {
+    match (version.unwrap_or(0), applied) {
+        (version, Some(applied)) if version < applied => Err(SignatureError::Rollback(url.to_string(), version, applied)),
+        _ => Ok(()),
+    }
+}
+
+//a new secret key written to secret_path, which must not exist yet. Returns the public key to trust
+pub fn generate(secret_path: &Path) -> Result<String, SignatureError> {
+    let key = SigningKey::generate(&mut rand_core::OsRng);
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(secret_path)?;
+    std::io::Write::write_all(&mut file, STANDARD.encode(key.to_bytes()).as_bytes())?;
+    Ok(STANDARD.encode(key.verifying_key().to_bytes()))
+}
+
+//writes <manifest>.sig next to the manifest, returns its path
+pub fn sign(manifest_path: &Path, secret_path: &Path) -> Result<String, SignatureError> {
+    let key = SigningKey::from_bytes(&decode(&std::fs::read_to_string(secret_path)?)?);
+    let signature = key.sign(&std::fs::read(manifest_path)?);
+    let signature_path = signature_url(&manifest_path.to_string_lossy());
+    std::fs::write(&signature_path, STANDARD.encode(signature.to_bytes()))?;
+    Ok(signature_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_manifests_signed_by_a_trusted_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let (secret, manifest) = (dir.path().join("manifest.key"), dir.path().join("files.json"));
+        let public = generate(&secret).ok().unwrap();
+        assert!(generate(&secret).is_err());
+        std::fs::write(&manifest, b"[]").unwrap();
+        let signature = std::fs::read_to_string(sign(&manifest, &secret).ok().unwrap()).unwrap();
+        let keys = trusted_keys(&[public]).ok().unwrap();
+
+        assert!(verify("files.json", b"[]", Some(&signature), &keys).is_ok());
+        assert!(matches!(verify("files.json", b"[ ]", Some(&signature), &keys), Err(SignatureError::BadSignature(_))));
+        assert!(matches!(verify("files.json", b"[]", None, &keys), Err(SignatureError::Unsigned(_))));
+        assert!(matches!(trusted_keys(&[String::from("bm90IGEga2V5")]), Err(SignatureError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn refuses_unverified_and_older_manifests_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = trusted_keys(&[generate(&dir.path().join("manifest.key")).ok().unwrap()]).ok().unwrap();
+
+        assert!(matches!(check("files.json", b"[]", None, &[], false), Err(SignatureError::NoTrustedKey(_))));
+        assert!(check("files.json", b"[]", None, &[], true).is_ok());
+        assert!(matches!(check("files.json", b"[]", None, &keys, false), Err(SignatureError::Unsigned(_))));
+        assert!(check("files.json", b"[]", None, &keys, true).is_ok());
+        assert!(matches!(check("files.json", b"[]", Some("AAAA"), &keys, true), Err(SignatureError::BadSignature(_))));
+        assert!(check_version("files.json", Some(3), Some(3)).is_ok());
+        assert!(check_version("files.json", Some(1), None).is_ok());
+        assert!(matches!(check_version("files.json", Some(2), Some(3)), Err(SignatureError::Rollback(_, 2, 3))));
+        assert!(matches!(check_version("files.json", None, Some(3)), Err(SignatureError::Rollback(_, 0, 3))));
+    }
+}
@@ -0,0 +1,125 @@
+//stand-in http server and fixture helpers for the tests
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub struct TestServer {
+    base: String,
+    //head of every request received, in order
+    requests: Arc<Mutex<Vec<String>>>,
+    quirks: Arc<Mutex<Quirks>>,
+}
+
+//misbehaviours of some routes, set after the start
+#[derive(Default)]
+struct Quirks {
+    //served whole, without Accept-Ranges
+    no_ranges: HashSet<String>,
+    //the next range asked is cut in the middle
+    fail_once: HashSet<String>,
+}
+
+impl TestServer {
+    //serves the given bodies on 127.0.0.1, any other path is a 404. Single byte ranges are honoured
+    pub async fn start(routes: Vec<(&str, Vec<u8>)>) -> TestServer {
+        let routes = routes.into_iter().map(|(path, body)| (path.to_string(), body)).collect();
+        TestServer::start_with(|_| routes).await
+    }
+
+    //same, for bodies pointing back at the server: routes are built from its base url
+    pub async fn start_with<F>(routes: F) -> TestServer
+    where
+        F: FnOnce(&str) -> Vec<(String, Vec<u8>)>
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let routes = Arc::new(routes(&base).into_iter().collect::<HashMap<_, _>>());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let quirks = Arc::new(Mutex::new(Quirks::default()));
+        let (received, server_quirks) = (requests.clone(), quirks.clone());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let (routes, received, quirks) = (routes.clone(), received.clone(), server_quirks.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buffer[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).into_owned();
+                    received.lock().unwrap().push(request.clone());
+                    let mut line = request.lines().next().unwrap_or_default().split(' ');
+                    let (method, path) = (line.next().unwrap_or_default(), line.next().unwrap_or_default());
+                    let (ranged, cut) = {
+                        let mut quirks = quirks.lock().unwrap();
+                        let ranged = !quirks.no_ranges.contains(path);
+                        (ranged, ranged && quirks.fail_once.remove(path))
+                    };
+                    let range = request.lines()
+                        .find_map(|l| l.to_ascii_lowercase().strip_prefix("range: bytes=").map(str::to_string))
+                        .and_then(|r| r.split_once('-').and_then(|(s, e)| Some((s.parse::<usize>().ok()?, e.parse::<usize>().ok()?))))
+                        .filter(|_| ranged);
+                    let response = match routes.get(path) {
+                        Some(body) => {
+                            let (status, body) = match range {
+                                Some((start, end)) => (
+                                    format!("206 Partial Content\r\nContent-Range: bytes {}-{}/{}", start, end, body.len()),
+                                    &body[start..=end.min(body.len() - 1)],
+                                ),
+                                None => (String::from("200 OK"), &body[..]),
+                            };
+                            let accept = if ranged { "Accept-Ranges: bytes\r\n" } else { "" };
+                            let mut response = format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n", status, accept, body.len()).into_bytes();
+                            if method != "HEAD" {
+                                //announced whole, closed halfway
+                                let sent = if range.is_some() && cut { body.len() / 2 } else { body.len() };
+                                response.extend_from_slice(&body[..sent]);
+                            }
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                    };
+                    let _ = socket.write_all(&response).await;
+                });
+            }
+        });
+        TestServer { base, requests, quirks }
+    }
+
+    //path is then served as if the server knew nothing of byte ranges
+    pub fn without_ranges(&self, path: &str) {
+        self.quirks.lock().unwrap().no_ranges.insert(path.to_string());
+    }
+
+    //the next range of path asked for breaks off halfway
+    pub fn fail_once(&self, path: &str) {
+        self.quirks.lock().unwrap().fail_once.insert(path.to_string());
+    }
+
+    //the requests whose first line contains path
+    pub fn requests(&self, path: &str) -> Vec<String> {
+        self.requests.lock().unwrap().iter()
+            .filter(|r| r.lines().next().is_some_and(|l| l.contains(path)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+}
+
+//zip archive made of the given (name, content) entries
+pub fn zip_fixture(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, content) in entries {
+        writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+        writer.write_all(content).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
@@ -0,0 +1,85 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use super::{rules_allow, Features, Platform, Rule, Version};
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ArgumentValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Argument {
+    Plain(String),
+    Conditional {
+        rules: Vec<Rule>,
+        value: ArgumentValue,
+    },
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Arguments {
+    #[serde(default)]
+    pub game: Vec<Argument>,
+    #[serde(default)]
+    pub jvm: Vec<Argument>,
+}
+
+//values of the ${...} placeholders
+pub type Variables = HashMap<&'static str, String>;
+
+//jvm arguments of versions older than 1.13, which only have "minecraftArguments"
+pub(super) const LEGACY_JVM_ARGUMENTS: [&str; 3] = ["-Djava.library.path=${natives_directory}", "-cp", "${classpath}"];
+
+pub fn substitute(template: &str, variables: &Variables) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let key = &rest[start + 2..start + end];
+        result.push_str(&rest[..start]);
+        match variables.get(key) {
+            Some(value) => result.push_str(value),
+            None => result.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn expand(arguments: &[Argument], platform: &Platform, features: &Features, variables: &Variables) -> Vec<String> {
+    let mut result = Vec::new();
+    for argument in arguments {
+        match argument {
+            Argument::Plain(value) => result.push(substitute(value, variables)),
+            Argument::Conditional { rules, value } if rules_allow(rules, platform, features) => match value {
+                ArgumentValue::One(value) => result.push(substitute(value, variables)),
+                ArgumentValue::Many(values) => result.extend(values.iter().map(|v| substitute(v, variables))),
+            },
+            Argument::Conditional { .. } => {}
+        }
+    }
+    result
+}
+
+impl Version {
+    pub fn jvm_arguments(&self, platform: &Platform, features: &Features, variables: &Variables) -> Vec<String> {
+        match &self.arguments {
+            Some(arguments) if !arguments.jvm.is_empty() => expand(&arguments.jvm, platform, features, variables),
+            _ => LEGACY_JVM_ARGUMENTS.iter().map(|a| substitute(a, variables)).collect(),
+        }
+    }
+
+    pub fn game_arguments(&self, platform: &Platform, features: &Features, variables: &Variables) -> Vec<String> {
+        match (&self.arguments, &self.minecraft_arguments) {
+            (Some(arguments), _) if !arguments.game.is_empty() => expand(&arguments.game, platform, features, variables),
+            (_, Some(legacy)) => legacy.split_whitespace().map(|a| substitute(a, variables)).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use sha1::Sha1;
+use crate::download::{file_to_hash, File, Files};
+use crate::env;
+use super::VersionError;
+
+#[derive(Deserialize, Debug)]
+pub struct AssetIndexRef {
+    pub id: String,
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetObject {
+    pub hash: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetIndex {
+    pub objects: HashMap<String, AssetObject>,
+    #[serde(default, rename = "virtual")]
+    pub is_virtual: bool,
+    #[serde(default)]
+    pub map_to_resources: bool,
+}
+
+impl AssetObject {
+    //objects are stored as objects/<first two hex chars>/<hash>, the hash being a sha1
+    fn relative_path(&self) -> Result<String, VersionError> {
+        if self.hash.len() != 40 || !self.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(VersionError::InvalidHash(self.hash.clone()));
+        }
+        Ok(format!("{}/{}", &self.hash[..2], self.hash))
+    }
+}
+
+impl AssetIndexRef {
+    //download (or reuse) assets/indexes/<id>.json and parse it
+    pub async fn fetch(&self, assets_dir: &Path) -> Result<AssetIndex, VersionError> {
+        let file = File {
+            url: self.url.clone(),
+            size: self.size,
+            hash: self.sha1.clone(),
+            path: format!("indexes/{}.json", self.id),
+            ..Default::default()
+        };
+        file.ensure(&assets_dir.to_string_lossy()).await?;
+        let bytes = std::fs::read(assets_dir.join(&file.path)).map_err(VersionError::Io)?;
+        serde_json::from_slice(&bytes).map_err(VersionError::Json)
+    }
+}
+
+impl AssetIndex {
+    //download plan relative to the assets directory, an object shared by several names is listed once
+    pub fn files(&self) -> Result<Files, VersionError> {
+        let mut seen = HashSet::new();
+        self.objects
+            .values()
+            .filter(|object| seen.insert(object.hash.as_str()))
+            .map(|object| {
+                let relative_path = object.relative_path()?;
+                Ok(File {
+                    url: format!("{}/{}", env::RESOURCES_URL, relative_path),
+                    size: object.size,
+                    hash: object.hash.clone(),
+                    path: format!("objects/{}", relative_path),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Files::from)
+    }
+
+    //legacy indexes want the files under their real names instead of their hash
+    pub fn materialise(&self, assets_dir: &Path, game_dir: &Path) -> Result<(), VersionError> {
+        let target = if self.map_to_resources {
+            game_dir.join("resources")
+        } else if self.is_virtual {
+            assets_dir.join("virtual").join("legacy")
+        } else {
+            return Ok(());
+        };
+        for (name, object) in &self.objects {
+            if !Path::new(name).components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+                continue;
+            }
+            let path = target.join(name);
+            //a copy edited in place keeps its size, the hash tells
+            let intact = path.metadata().is_ok_and(|m| m.len() == object.size)
+                && file_to_hash::<Sha1, _>(&path).is_ok_and(|hash| hex::encode(hash) == object.hash);
+            if intact {
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(VersionError::Io)?;
+            }
+            let source = assets_dir.join("objects").join(object.relative_path()?);
+            std::fs::copy(source, path).map_err(VersionError::Io)?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn download_assets<F>(index: &AssetIndexRef, assets_dir: &Path, game_dir: &Path, callback_send_delta: F) -> Result<AssetIndex, VersionError>
+where
+    F: Fn(u32) -> bool
+{
+    let index = index.fetch(assets_dir).await?;
+    index.files()?.sync(&assets_dir.to_string_lossy(), callback_send_delta).await?;
+    index.materialise(assets_dir, game_dir)?;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1::Digest;
+
+    #[test]
+    fn rejects_objects_without_a_sha1() {
+        let index: AssetIndex = serde_json::from_value(serde_json::json!({ "objects": {
+            "icons/icon_16x16.png": { "hash": "bdf48ef6b5d0d23bbb02e17d04865216179f510a", "size": 3665 },
+        }})).unwrap();
+        let files = index.files().ok().unwrap();
+        assert_eq!(files.iter().next().unwrap().path, "objects/bd/bdf48ef6b5d0d23bbb02e17d04865216179f510a");
+
+        for hash in ["", "a", "../../../../etc/passwd", "é9f48ef6b5d0d23bbb02e17d04865216179f510a"] {
+            let object = AssetObject { hash: hash.to_string(), size: 0 };
+            assert!(matches!(object.relative_path(), Err(VersionError::InvalidHash(_))));
+        }
+    }
+
+    const SOUND: &[u8] = b"OggS sound";
+    const ICON: &[u8] = b"PNG icon";
+
+    fn index(base: &str, flags: serde_json::Value) -> (AssetIndexRef, Vec<u8>) {
+        let mut json = serde_json::json!({ "objects": {
+            "sound/step/grass1.ogg": { "hash": hex::encode(Sha1::digest(SOUND)), "size": SOUND.len() },
+            "icons/icon_16x16.png": { "hash": hex::encode(Sha1::digest(ICON)), "size": ICON.len() },
+            "../escaped.png": { "hash": hex::encode(Sha1::digest(ICON)), "size": ICON.len() },
+        }});
+        json.as_object_mut().unwrap().extend(flags.as_object().unwrap().clone());
+        let bytes = serde_json::to_vec(&json).unwrap();
+        let reference = AssetIndexRef {
+            id: String::from("pre-1.6"),
+            sha1: hex::encode(Sha1::digest(&bytes)),
+            size: bytes.len() as u64,
+            url: format!("{}/indexes/pre-1.6.json", base),
+        };
+        (reference, bytes)
+    }
+
+    //"<aa>/<hash>", below the resources url and the objects directory alike
+    fn hashed(content: &[u8]) -> String {
+        let hash = hex::encode(Sha1::digest(content));
+        format!("{}/{}", &hash[..2], hash)
+    }
+
+    fn object(content: &[u8]) -> String {
+        format!("objects/{}", hashed(content))
+    }
+
+    #[tokio::test]
+    async fn downloads_each_object_once() {
+        let server = crate::test_server::TestServer::start_with(|base| vec![
+            (String::from("/indexes/pre-1.6.json"), index(base, serde_json::json!({})).1),
+            (format!("/{}", hashed(SOUND)), SOUND.to_vec()),
+            (format!("/{}", hashed(ICON)), ICON.to_vec()),
+        ]).await;
+        let assets = tempfile::tempdir().unwrap();
+        let root = assets.path().to_string_lossy();
+        let (reference, _) = index(&server.url(""), serde_json::json!({}));
+
+        let index = reference.fetch(assets.path()).await.ok().unwrap();
+        let mut files = index.files().ok().unwrap();
+        assert_eq!(files.iter().filter(|f| f.url.starts_with(env::RESOURCES_URL)).count(), 2);
+        //the same paths, served locally
+        for file in files.iter_mut() {
+            file.url = server.url(&file.url[env::RESOURCES_URL.len()..]);
+        }
+        files.sync(&root, |_| false).await.ok().unwrap();
+
+        assert_eq!(std::fs::read(assets.path().join(object(SOUND))).unwrap(), SOUND);
+        assert_eq!(std::fs::read(assets.path().join(object(ICON))).unwrap(), ICON);
+        assert_eq!(server.requests(&hashed(ICON)).len(), 1);
+
+        //present and verified, neither the index nor the objects are asked again
+        let before = server.requests("").len();
+        reference.fetch(assets.path()).await.ok().unwrap();
+        files.sync(&root, |_| false).await.ok().unwrap();
+        assert_eq!(server.requests("").len(), before);
+    }
+
+    #[test]
+    fn materialises_legacy_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (assets, game) = (dir.path().join("assets"), dir.path().join("game"));
+        for content in [SOUND, ICON] {
+            std::fs::create_dir_all(assets.join(object(content)).parent().unwrap()).unwrap();
+            std::fs::write(assets.join(object(content)), content).unwrap();
+        }
+        let parse = |flags| serde_json::from_slice::<AssetIndex>(&index("", flags).1).unwrap();
+
+        parse(serde_json::json!({})).materialise(&assets, &game).ok().unwrap();
+        assert!(!assets.join("virtual").exists() && !game.join("resources").exists());
+
+        parse(serde_json::json!({ "virtual": true })).materialise(&assets, &game).ok().unwrap();
+        assert_eq!(std::fs::read(assets.join("virtual/legacy/sound/step/grass1.ogg")).unwrap(), SOUND);
+        assert!(!assets.join("virtual/escaped.png").exists());
+
+        let resources = parse(serde_json::json!({ "map_to_resources": true }));
+        resources.materialise(&assets, &game).ok().unwrap();
+        assert_eq!(std::fs::read(game.join("resources/icons/icon_16x16.png")).unwrap(), ICON);
+
+        //same size, other content: copied again
+        std::fs::write(game.join("resources/icons/icon_16x16.png"), b"PNG edit").unwrap();
+        resources.materialise(&assets, &game).ok().unwrap();
+        assert_eq!(std::fs::read(game.join("resources/icons/icon_16x16.png")).unwrap(), ICON);
+    }
+}
@@ -0,0 +1,275 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use sha1::Sha1;
+use crate::config::LoaderConfig;
+use crate::download::{file_to_hash, DownloadError, Files};
+use crate::instance::{Loader, LoaderKind};
+use crate::{cache, log, network};
+use crate::maven::{Coordinates, MavenArtifact};
+use super::{resolve_libraries, Features, Library, Platform, Version, VersionError};
+
+#[derive(Deserialize, Debug)]
+pub struct DataEntry {
+    pub client: String,
+}
+
+//a step of the installer: java -cp <jar;classpath> <Main-Class of jar> <args>
+#[derive(Deserialize, Debug)]
+pub struct Processor {
+    pub jar: String,
+    #[serde(default)]
+    pub classpath: Vec<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    //file -> expected sha1, both may be placeholders
+    #[serde(default)]
+    pub outputs: HashMap<String, String>,
+    //runs on both sides when unset
+    pub sides: Option<Vec<String>>,
+}
+
+//install_profile.json of the 1.13+ installers, older ones are not supported
+#[derive(Deserialize, Debug)]
+pub struct InstallProfile {
+    pub minecraft: String,
+    //entry of the installer holding the version json
+    pub json: String,
+    #[serde(default)]
+    pub data: HashMap<String, DataEntry>,
+    #[serde(default)]
+    pub processors: Vec<Processor>,
+    #[serde(default)]
+    pub libraries: Vec<Library>,
+}
+
+fn installer_coordinates(config: &LoaderConfig, loader: &Loader, game_version: &str) -> Option<(String, String)> {
+    match loader.kind {
+        LoaderKind::Forge => Some((
+            format!("net.minecraftforge:forge:{}-{}:installer", game_version, loader.version),
+            config.forge_maven_url.clone(),
+        )),
+        LoaderKind::NeoForge => Some((
+            format!("net.neoforged:neoforge:{}:installer", loader.version),
+            config.neoforge_maven_url.clone(),
+        )),
+        LoaderKind::Fabric | LoaderKind::Quilt => None,
+    }
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<std::fs::File>, name: &str) -> Result<Vec<u8>, VersionError> {
+    let mut entry = archive.by_name(name.trim_start_matches('/')).map_err(VersionError::Zip)?;
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes).map_err(VersionError::Io)?;
+    Ok(bytes)
+}
+
+//the installer ships the loader's own jars under maven/, they have no download url
+fn extract_maven(archive: &mut zip::ZipArchive<std::fs::File>, libraries_dir: &Path) -> Result<(), VersionError> {
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(VersionError::Zip)?;
+        let Some(relative) = entry.enclosed_name().and_then(|p| p.strip_prefix("maven").ok()).map(Path::to_path_buf) else {
+            continue;
+        };
+        if entry.is_dir() || relative.as_os_str().is_empty() {
+            continue;
+        }
+        let path = libraries_dir.join(relative);
+        std::fs::create_dir_all(path.parent().expect("library has a parent")).map_err(VersionError::Io)?;
+        let mut file = cache::create_file(&path).map_err(VersionError::Io)?;
+        std::io::copy(&mut entry, &mut file).map_err(VersionError::Io)?;
+    }
+    Ok(())
+}
+
+fn library_path(libraries_dir: &Path, coordinates: &str) -> Result<PathBuf, VersionError> {
+    Coordinates::parse(coordinates)
+        .map(|c| libraries_dir.join(c.path()))
+        .ok_or_else(|| VersionError::Processor(format!("invalid coordinates {}", coordinates)))
+}
+
+//"{KEY}" is looked up in the data, "[coordinates]" is a library, anything else is literal
+fn resolve_argument(argument: &str, data: &HashMap<String, String>, libraries_dir: &Path) -> Result<String, VersionError> {
+    if let Some(key) = argument.strip_prefix('{').and_then(|a| a.strip_suffix('}')) {
+        return data.get(key).cloned().ok_or_else(|| VersionError::Processor(format!("unknown data {}", key)));
+    }
+    if let Some(coordinates) = argument.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+        return Ok(library_path(libraries_dir, coordinates)?.to_string_lossy().into_owned());
+    }
+    Ok(argument.to_string())
+}
+
+//data values: "[coordinates]" of a library, "'literal'", or "/entry" of the installer extracted to temp_dir
+fn resolve_data(profile: &InstallProfile, archive: &mut zip::ZipArchive<std::fs::File>, libraries_dir: &Path, temp_dir: &Path) -> Result<HashMap<String, String>, VersionError> {
+    let mut data = HashMap::new();
+    for (key, entry) in &profile.data {
+        let value = &entry.client;
+        let resolved = if let Some(literal) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+            literal.to_string()
+        } else if value.starts_with('/') {
+            let path = temp_dir.join(value.trim_start_matches('/'));
+            std::fs::create_dir_all(path.parent().expect("data file has a parent")).map_err(VersionError::Io)?;
+            std::fs::write(&path, read_entry(archive, value)?).map_err(VersionError::Io)?;
+            path.to_string_lossy().into_owned()
+        } else {
+            resolve_argument(value, &data, libraries_dir)?
+        };
+        data.insert(key.clone(), resolved);
+    }
+    Ok(data)
+}
+
+fn main_class(jar: &Path) -> Result<String, VersionError> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(jar).map_err(VersionError::Io)?).map_err(VersionError::Zip)?;
+    let mut manifest = String::new();
+    archive.by_name("META-INF/MANIFEST.MF").map_err(VersionError::Zip)?
+        .read_to_string(&mut manifest).map_err(VersionError::Io)?;
+    manifest.lines()
+        .find_map(|line| line.strip_prefix("Main-Class:"))
+        .map(|class| class.trim().to_string())
+        .ok_or_else(|| VersionError::Processor(format!("no Main-Class in {}", jar.display())))
+}
+
+fn sha1_of(path: &Path) -> Option<String> {
+    file_to_hash::<Sha1, _>(path).ok().map(hex::encode)
+}
+
+//output path -> expected sha1, placeholders resolved
+fn outputs(processor: &Processor, data: &HashMap<String, String>, libraries_dir: &Path) -> Result<Vec<(PathBuf, String)>, VersionError> {
+    processor.outputs.iter().map(|(file, hash)| {
+        let hash = resolve_argument(hash, data, libraries_dir)?;
+        Ok((PathBuf::from(resolve_argument(file, data, libraries_dir)?), hash.trim_matches('\'').to_string()))
+    }).collect()
+}
+
+async fn run_processor(processor: &Processor, java: &Path, data: &HashMap<String, String>, libraries_dir: &Path) -> Result<(), VersionError> {
+    let outputs = outputs(processor, data, libraries_dir)?;
+    if !outputs.is_empty() && outputs.iter().all(|(path, hash)| sha1_of(path).as_deref() == Some(hash)) {
+        log!("INFO", processor.jar, "outputs up to date, skipped");
+        return Ok(());
+    }
+    let jar = library_path(libraries_dir, &processor.jar)?;
+    let mut classpath = vec![jar.clone()];
+    for coordinates in &processor.classpath {
+        classpath.push(library_path(libraries_dir, coordinates)?);
+    }
+    let classpath = std::env::join_paths(classpath).map_err(|e| VersionError::Processor(e.to_string()))?;
+    let arguments = processor.args.iter()
+        .map(|a| resolve_argument(a, data, libraries_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    log!("INFO", processor.jar, "running");
+    let mut command = tokio::process::Command::new(java);
+    command.arg("-cp").arg(classpath).arg(main_class(&jar)?).args(&arguments);
+    #[cfg(windows)]
+    {
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let output = command.output().await.map_err(VersionError::Io)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let tail = stderr.lines().rev().take(5).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>().join("\n");
+        return Err(VersionError::Processor(format!("{} failed ({}):\n{}", processor.jar, output.status, tail)));
+    }
+    for (path, hash) in outputs {
+        if sha1_of(&path).as_deref() != Some(hash.as_str()) {
+            let _ = std::fs::remove_file(&path);
+            return Err(VersionError::Processor(format!("{} produced a bad {}", processor.jar, path.display())));
+        }
+    }
+    Ok(())
+}
+
+//run the Forge/NeoForge installer for the client: download its libraries, run its processors with the
+//given java, then write versions/<id>/<id>.json. The version json is written last, its presence means
+//the loader is installed
+pub async fn install_forge<F>(config: &LoaderConfig, loader: &Loader, game: &Version, work_dir: &Path, java: &Path, callback_send_delta: F) -> Result<Version, VersionError>
+where
+    F: Fn(u32) -> bool
+{
+    let (versions_dir, libraries_dir) = (work_dir.join("versions"), work_dir.join("libraries"));
+    let id = loader.version_id(&game.id);
+    let version_path = versions_dir.join(&id).join(format!("{}.json", id));
+    if version_path.exists() {
+        return Version::from_path(version_path);
+    }
+    if network::is_offline() {
+        return Err(DownloadError::missing(version_path).into());
+    }
+    let (coordinates, repository) = installer_coordinates(config, loader, &game.id)
+        .ok_or_else(|| VersionError::UnsupportedLoader(id.clone()))?;
+
+    let installer = MavenArtifact {
+        coordinates: Coordinates::parse(&coordinates).expect("installer coordinates are valid"),
+        repository: Some(repository.clone()),
+        sha1: None,
+        size: 0,
+    };
+    let installer = installer.resolve(network::client(), &[]).await?;
+    let installer_path = libraries_dir.join(&installer.path);
+    Files::from(vec![installer]).sync(&libraries_dir.to_string_lossy(), &callback_send_delta).await?;
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&installer_path).map_err(VersionError::Io)?).map_err(VersionError::Zip)?;
+
+    let profile_bytes = read_entry(&mut archive, "install_profile.json")?;
+    let raw: serde_json::Value = serde_json::from_slice(&profile_bytes).map_err(VersionError::Json)?;
+    if raw.get("install").is_some() {
+        return Err(VersionError::UnsupportedLoader(format!("{} (legacy installer)", id)));
+    }
+    let profile: InstallProfile = serde_json::from_value(raw).map_err(VersionError::Json)?;
+    if profile.minecraft != game.id {
+        return Err(VersionError::UnsupportedLoader(format!("{} is made for {}", id, profile.minecraft)));
+    }
+    let version_bytes = read_entry(&mut archive, &profile.json)?;
+    let version: Version = serde_json::from_slice(&version_bytes).map_err(VersionError::Json)?;
+
+    extract_maven(&mut archive, &libraries_dir)?;
+    let (platform, features) = (Platform::current(), Features::new());
+    //what the processors need, the libraries of the version itself are downloaded at launch
+    let mut plan = resolve_libraries(&profile.libraries, &platform, &features);
+    plan.resolve_maven(std::slice::from_ref(&repository), &libraries_dir).await?;
+    plan.files.sync(&libraries_dir.to_string_lossy(), &callback_send_delta).await?;
+
+    let temp_dir = tempfile::tempdir().map_err(VersionError::Io)?;
+    let mut data = resolve_data(&profile, &mut archive, &libraries_dir, temp_dir.path())?;
+    let client_jar = versions_dir.join(&game.id).join(format!("{}.jar", game.id));
+    data.extend([
+        ("SIDE", String::from("client")),
+        ("MINECRAFT_JAR", client_jar.to_string_lossy().into_owned()),
+        ("MINECRAFT_VERSION", game.id.clone()),
+        ("ROOT", work_dir.to_string_lossy().into_owned()),
+        ("INSTALLER", installer_path.to_string_lossy().into_owned()),
+        ("LIBRARY_DIR", libraries_dir.to_string_lossy().into_owned()),
+    ].map(|(key, value)| (key.to_string(), value)));
+
+    let client_side = |p: &&Processor| p.sides.as_ref().is_none_or(|sides| sides.iter().any(|s| s == "client"));
+    for processor in profile.processors.iter().filter(client_side) {
+        run_processor(processor, java, &data, &libraries_dir).await?;
+    }
+
+    let version_path = versions_dir.join(&version.id).join(format!("{}.json", version.id));
+    std::fs::create_dir_all(version_path.parent().expect("version file has a parent")).map_err(VersionError::Io)?;
+    std::fs::write(&version_path, version_bytes).map_err(VersionError::Io)?;
+    log!("OK", version.id, "installed");
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_processor_arguments() {
+        let libraries_dir = Path::new("libraries");
+        let data = HashMap::from([(String::from("SIDE"), String::from("client"))]);
+
+        assert_eq!(resolve_argument("{SIDE}", &data, libraries_dir).ok().unwrap(), "client");
+        assert_eq!(resolve_argument("--task", &data, libraries_dir).ok().unwrap(), "--task");
+        assert_eq!(
+            PathBuf::from(resolve_argument("[de.oceanlabs.mcp:mcp_config:1.20.1:mappings@txt]", &data, libraries_dir).ok().unwrap()),
+            libraries_dir.join("de/oceanlabs/mcp/mcp_config/1.20.1/mcp_config-1.20.1-mappings.txt"),
+        );
+        assert!(resolve_argument("{MISSING}", &data, libraries_dir).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use crate::download::{DownloadError, File, Files};
+use crate::maven::{self, Coordinates, MavenArtifact};
+use crate::log;
+use super::{rules_allow, Features, Platform, Rule};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Artifact {
+    pub path: Option<String>,
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LibraryDownloads {
+    pub artifact: Option<Artifact>,
+    #[serde(default)]
+    pub classifiers: HashMap<String, Artifact>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Extract {
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Library {
+    pub name: String,
+    #[serde(default)]
+    pub downloads: LibraryDownloads,
+    #[serde(default)]
+    pub natives: HashMap<String, String>,
+    pub extract: Option<Extract>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    //maven repository of libraries without "downloads", as in loader profiles
+    pub url: Option<String>,
+    pub sha1: Option<String>,
+    pub size: Option<u64>,
+}
+
+//a native jar to unpack before launch
+pub struct Native {
+    pub path: String,
+    pub exclude: Vec<String>,
+}
+
+//what the libraries of a version need on this platform, paths are relative to the libraries directory
+pub struct LibraryPlan {
+    pub files: Files,
+    pub classpath: Vec<String>,
+    pub natives: Vec<Native>,
+    //libraries without "downloads", still to be found in a repository
+    pub maven: Vec<MavenArtifact>,
+}
+
+impl Library {
+    pub fn is_allowed(&self, platform: &Platform, features: &Features) -> bool {
+        rules_allow(&self.rules, platform, features)
+    }
+
+    //classifier of the legacy "natives" map, ${arch} replaced by 32 or 64
+    fn native_classifier(&self, platform: &Platform) -> Option<String> {
+        self.natives
+            .get(platform.name)
+            .map(|classifier| classifier.replace("${arch}", platform.bits()))
+    }
+
+    //1.19+ format: the natives are their own library, e.g. "org.lwjgl:lwjgl:3.3.1:natives-windows"
+    fn is_native_artifact(&self) -> bool {
+        self.name
+            .split(':')
+            .nth(3)
+            .is_some_and(|classifier| classifier.starts_with("natives-"))
+    }
+
+    fn exclude(&self) -> Vec<String> {
+        self.extract.clone().unwrap_or_default().exclude
+    }
+}
+
+fn artifact_file(name: &str, artifact: &Artifact) -> Option<File> {
+    let path = match &artifact.path {
+        Some(path) => path.clone(),
+        None => Coordinates::parse(name)?.path(),
+    };
+    Some(File {
+        url: artifact.url.clone(),
+        size: artifact.size,
+        hash: artifact.sha1.clone(),
+        path,
+        ..Default::default()
+    })
+}
+
+pub fn resolve_libraries(libraries: &[Library], platform: &Platform, features: &Features) -> LibraryPlan {
+    let mut files = Vec::new();
+    let mut classpath = Vec::new();
+    let mut natives = Vec::new();
+    let mut maven = Vec::new();
+    let mut seen = HashSet::new();
+    for library in libraries.iter().filter(|l| l.is_allowed(platform, features)) {
+        if let Some(artifact) = &library.downloads.artifact {
+            let Some(file) = artifact_file(&library.name, artifact) else {
+                log!("WARN", library.name, "invalid library name");
+                continue;
+            };
+            if library.is_native_artifact() {
+                natives.push(Native { path: file.path.clone(), exclude: library.exclude() });
+            }
+            if seen.insert(file.path.clone()) {
+                classpath.push(file.path.clone());
+                //an empty url marks a jar the loader's installer produces, there is nothing to download
+                if !file.url.is_empty() {
+                    files.push(file);
+                }
+            }
+        } else if library.natives.is_empty() {
+            //known by its coordinates only, see LibraryPlan::resolve_maven
+            let Some(coordinates) = Coordinates::parse(&library.name) else {
+                log!("WARN", library.name, "invalid library name");
+                continue;
+            };
+            if seen.insert(coordinates.path()) {
+                classpath.push(coordinates.path());
+                maven.push(MavenArtifact {
+                    coordinates,
+                    repository: library.url.clone(),
+                    sha1: library.sha1.clone(),
+                    size: library.size.unwrap_or(0),
+                });
+            }
+        }
+        if let Some(classifier) = library.native_classifier(platform) {
+            let name = format!("{}:{}", library.name, classifier);
+            if let Some(file) = library.downloads.classifiers.get(&classifier).and_then(|artifact| artifact_file(&name, artifact)) {
+                natives.push(Native { path: file.path.clone(), exclude: library.exclude() });
+                if seen.insert(file.path.clone()) {
+                    files.push(file);
+                }
+                continue;
+            }
+            //old version jsons don't list downloads, the natives come from a repository too
+            let Some(coordinates) = Coordinates::parse(&name) else {
+                log!("WARN", library.name, "missing natives {}", classifier);
+                continue;
+            };
+            natives.push(Native { path: coordinates.path(), exclude: library.exclude() });
+            if seen.insert(coordinates.path()) {
+                maven.push(MavenArtifact { coordinates, repository: library.url.clone(), sha1: None, size: 0 });
+            }
+        }
+    }
+    LibraryPlan {
+        files: files.into(),
+        classpath,
+        natives,
+        maven,
+    }
+}
+
+impl LibraryPlan {
+    //find the maven libraries in the repositories and add them to the files to download
+    pub async fn resolve_maven(&mut self, repositories: &[String], libraries_dir: &std::path::Path) -> Result<(), DownloadError> {
+        let resolved = maven::resolve_all(&self.maven, repositories, libraries_dir).await?;
+        self.files.files.extend(resolved);
+        self.maven.clear();
+        Ok(())
+    }
+
+    pub fn classpath_in(&self, libraries_dir: &std::path::Path) -> Vec<PathBuf> {
+        self.classpath.iter().map(|path| libraries_dir.join(path)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LWJGL_3: &str = "org/lwjgl/lwjgl/3.3.1/lwjgl-3.3.1.jar";
+    const LWJGL_3_LINUX: &str = "org/lwjgl/lwjgl/3.3.1/lwjgl-3.3.1-natives-linux.jar";
+    const TWITCH_32: &str = "tv/twitch/twitch-platform/6.5/twitch-platform-6.5-natives-windows-32.jar";
+    const TWITCH_64: &str = "tv/twitch/twitch-platform/6.5/twitch-platform-6.5-natives-windows-64.jar";
+
+    fn download(path: &str) -> serde_json::Value {
+        serde_json::json!({
+            "path": path,
+            "sha1": "0000000000000000000000000000000000000000",
+            "size": 1,
+            "url": format!("https://libraries.minecraft.net/{}", path),
+        })
+    }
+
+    //entries as found in the version jsons of 1.8.9, 1.12.2 and 1.19, and in a fabric profile
+    fn libraries() -> Vec<Library> {
+        serde_json::from_value(serde_json::json!([
+            { "name": "org.lwjgl:lwjgl:3.3.1", "downloads": { "artifact": download(LWJGL_3) } },
+            {
+                "name": "org.lwjgl:lwjgl:3.3.1:natives-linux",
+                "downloads": { "artifact": download(LWJGL_3_LINUX) },
+                "rules": [{ "action": "allow", "os": { "name": "linux" } }],
+            },
+            {
+                "name": "org.lwjgl:lwjgl:3.3.1:natives-windows",
+                "downloads": { "artifact": download("org/lwjgl/lwjgl/3.3.1/lwjgl-3.3.1-natives-windows.jar") },
+                "rules": [{ "action": "allow", "os": { "name": "windows" } }],
+            },
+            {
+                "name": "tv.twitch:twitch-platform:6.5",
+                "downloads": { "classifiers": {
+                    "natives-osx": download("tv/twitch/twitch-platform/6.5/twitch-platform-6.5-natives-osx.jar"),
+                    "natives-windows-32": download(TWITCH_32),
+                    "natives-windows-64": download(TWITCH_64),
+                }},
+                "natives": { "osx": "natives-osx", "windows": "natives-windows-${arch}" },
+                "extract": { "exclude": ["META-INF/"] },
+                "rules": [{ "action": "allow" }, { "action": "disallow", "os": { "name": "linux" } }],
+            },
+            {
+                "name": "org.lwjgl.lwjgl:lwjgl:2.9.0",
+                "downloads": { "artifact": download("org/lwjgl/lwjgl/lwjgl/2.9.0/lwjgl-2.9.0.jar") },
+                "rules": [{ "action": "allow", "os": { "name": "osx", "version": "^10\\.5\\.\\d$" } }],
+            },
+            { "name": "net.fabricmc:fabric-loader:0.14.21", "url": "https://maven.fabricmc.net/" },
+            { "name": "org.lwjgl:lwjgl:3.3.1", "downloads": { "artifact": download(LWJGL_3) } },
+        ])).unwrap()
+    }
+
+    fn platform(name: &'static str, arch: &'static str) -> Platform {
+        Platform { name, arch, version: String::from("10.0") }
+    }
+
+    //each native jar, followed by what its extraction leaves out
+    fn natives(plan: &LibraryPlan) -> Vec<String> {
+        plan.natives.iter().map(|n| [&[n.path.clone()][..], &n.exclude].concat().join(" -")).collect()
+    }
+
+    #[test]
+    fn resolves_the_libraries_of_the_platform() {
+        let fabric = "net/fabricmc/fabric-loader/0.14.21/fabric-loader-0.14.21.jar";
+        let windows = "org/lwjgl/lwjgl/3.3.1/lwjgl-3.3.1-natives-windows.jar";
+        let twitch_32 = format!("{} -META-INF/", TWITCH_32);
+        let twitch_64 = format!("{} -META-INF/", TWITCH_64);
+        let cases = [
+            (platform("linux", "x86_64"), vec![LWJGL_3, LWJGL_3_LINUX, fabric], vec![LWJGL_3_LINUX], vec![LWJGL_3, LWJGL_3_LINUX]),
+            (platform("windows", "x86"), vec![LWJGL_3, windows, fabric], vec![windows, &twitch_32], vec![LWJGL_3, windows, TWITCH_32]),
+            (platform("windows", "x86_64"), vec![LWJGL_3, windows, fabric], vec![windows, &twitch_64], vec![LWJGL_3, windows, TWITCH_64]),
+        ];
+        let libraries = libraries();
+        for (platform, classpath, expected_natives, files) in cases {
+            let plan = resolve_libraries(&libraries, &platform, &Features::new());
+            assert_eq!(plan.classpath, classpath, "{} {}", platform.name, platform.arch);
+            assert_eq!(natives(&plan), expected_natives, "{} {}", platform.name, platform.arch);
+            assert_eq!(plan.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), files, "{} {}", platform.name, platform.arch);
+            assert_eq!(plan.maven.iter().map(|m| m.coordinates.path()).collect::<Vec<_>>(), [fabric]);
+        }
+    }
+
+    #[test]
+    fn resolves_the_natives_of_old_jsons_from_their_coordinates() {
+        let libraries: Vec<Library> = serde_json::from_value(serde_json::json!([{
+            "name": "org.lwjgl.lwjgl:lwjgl-platform:2.9.0",
+            "natives": { "linux": "natives-linux", "osx": "natives-osx", "windows": "natives-windows" },
+            "extract": { "exclude": ["META-INF/"] },
+        }])).unwrap();
+
+        let plan = resolve_libraries(&libraries, &platform("linux", "x86_64"), &Features::new());
+
+        let jar = "org/lwjgl/lwjgl/lwjgl-platform/2.9.0/lwjgl-platform-2.9.0-natives-linux.jar";
+        assert_eq!(natives(&plan), [format!("{} -META-INF/", jar)]);
+        assert_eq!(plan.maven.iter().map(|m| m.coordinates.path()).collect::<Vec<_>>(), [jar]);
+        assert!(plan.classpath.is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+use std::path::Path;
+use crate::config::LoaderConfig;
+use crate::download::DownloadError;
+use crate::instance::{Loader, LoaderKind};
+use crate::{log, network};
+use crate::maven::Coordinates;
+use super::{Argument, Arguments, Version, VersionError, LEGACY_JVM_ARGUMENTS};
+
+fn meta_url<'a>(config: &'a LoaderConfig, loader: &Loader) -> Result<&'a str, VersionError> {
+    match loader.kind {
+        LoaderKind::Fabric => Ok(&config.fabric_meta_url),
+        LoaderKind::Quilt => Ok(&config.quilt_meta_url),
+        LoaderKind::Forge | LoaderKind::NeoForge => Err(VersionError::UnsupportedLoader(loader.version_id(""))),
+    }
+}
+
+//download (or reuse) the loader profile as versions/<id>/<id>.json, where the official launcher finds it too
+pub async fn install_loader(config: &LoaderConfig, loader: &Loader, game_version: &str, versions_dir: &Path) -> Result<Version, VersionError> {
+    let id = loader.version_id(game_version);
+    let path = versions_dir.join(&id).join(format!("{}.json", id));
+    if path.exists() {
+        return Version::from_path(path);
+    }
+    if network::is_offline() {
+        return Err(DownloadError::missing(path).into());
+    }
+    let url = format!("{}/versions/loader/{}/{}/profile/json", meta_url(config, loader)?.trim_end_matches('/'), game_version, loader.version);
+    log!("INFO", id, "fetching {}", url);
+    let resp = network::get_json(&url).await.and_then(|r| r.error_for_status()).map_err(DownloadError::Reqwest)?;
+    let bytes = network::body(resp).await?;
+    let profile: Version = serde_json::from_slice(&bytes).map_err(VersionError::Json)?;
+    //saved under the id the profile declares, which is the one the conventional id predicts
+    let path = versions_dir.join(&profile.id).join(format!("{}.json", profile.id));
+    std::fs::create_dir_all(path.parent().expect("version file has a parent")).map_err(VersionError::Io)?;
+    std::fs::write(&path, &bytes).map_err(VersionError::Io)?;
+    Ok(profile)
+}
+
+fn library_key(name: &str) -> String {
+    Coordinates::parse(name).map_or_else(|| name.to_string(), |c| c.key())
+}
+
+impl Version {
+    //a loader profile completed by the version it "inheritsFrom": its libraries come first and replace
+    //other versions of the same artifact, its arguments are appended to the parent's
+    pub fn merge(self, parent: Version) -> Version {
+        let keys = self.libraries.iter().map(|l| library_key(&l.name)).collect::<HashSet<_>>();
+        let mut libraries = self.libraries;
+        libraries.extend(parent.libraries.into_iter().filter(|l| !keys.contains(&library_key(&l.name))));
+
+        let arguments = match (parent.arguments, self.arguments) {
+            (None, None) => None,
+            (parent_arguments, child) => {
+                let mut arguments = parent_arguments.unwrap_or_else(|| Arguments {
+                    game: Vec::new(),
+                    //versions older than 1.13 rely on the implicit classpath arguments
+                    jvm: LEGACY_JVM_ARGUMENTS.iter().map(|a| Argument::Plain(a.to_string())).collect(),
+                });
+                if let Some(child) = child {
+                    arguments.game.extend(child.game);
+                    arguments.jvm.extend(child.jvm);
+                }
+                Some(arguments)
+            }
+        };
+
+        Version {
+            id: self.id,
+            inherits_from: None,
+            jar: self.jar.or(parent.jar).or(Some(parent.id)),
+            libraries,
+            asset_index: self.asset_index.or(parent.asset_index),
+            assets: self.assets.or(parent.assets),
+            main_class: self.main_class,
+            arguments,
+            minecraft_arguments: self.minecraft_arguments.or(parent.minecraft_arguments),
+            downloads: if self.downloads.client.is_some() { self.downloads } else { parent.downloads },
+            java_version: self.java_version.or(parent.java_version),
+            version_type: if self.version_type.is_empty() { parent.version_type } else { self.version_type },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(json: serde_json::Value) -> Version {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn merges_a_loader_profile_with_its_parent() {
+        let parent = version(serde_json::json!({
+            "id": "1.20.1",
+            "mainClass": "net.minecraft.client.main.Main",
+            "type": "release",
+            "assets": "5",
+            "downloads": { "client": { "sha1": "abc", "size": 1, "url": "https://example.com/client.jar" } },
+            "arguments": { "game": ["--username", "${auth_player_name}"], "jvm": ["-cp", "${classpath}"] },
+            "libraries": [
+                { "name": "org.ow2.asm:asm:9.3" },
+                { "name": "com.mojang:brigadier:1.1.8" },
+            ],
+        }));
+        let profile = version(serde_json::json!({
+            "id": "fabric-loader-0.15.7-1.20.1",
+            "inheritsFrom": "1.20.1",
+            "mainClass": "net.fabricmc.loader.impl.launch.knot.KnotClient",
+            "arguments": { "game": [], "jvm": ["-DFabricMcEmu= net.minecraft.client.main.Main "] },
+            "libraries": [
+                { "name": "org.ow2.asm:asm:9.6", "url": "https://maven.fabricmc.net/" },
+                { "name": "net.fabricmc:fabric-loader:0.15.7", "url": "https://maven.fabricmc.net/" },
+            ],
+        }));
+
+        let merged = profile.merge(parent);
+
+        assert_eq!(merged.id, "fabric-loader-0.15.7-1.20.1");
+        assert_eq!(merged.main_class, "net.fabricmc.loader.impl.launch.knot.KnotClient");
+        assert_eq!(merged.version_type, "release");
+        assert_eq!(merged.client_file().unwrap().path, "1.20.1/1.20.1.jar");
+        let names = merged.libraries.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["org.ow2.asm:asm:9.6", "net.fabricmc:fabric-loader:0.15.7", "com.mojang:brigadier:1.1.8"]);
+        let arguments = merged.arguments.unwrap();
+        assert_eq!((arguments.game.len(), arguments.jvm.len()), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn installs_the_profile_from_the_meta_server() {
+        let profile = serde_json::json!({
+            "id": "quilt-loader-0.23.1-1.20.1",
+            "inheritsFrom": "1.20.1",
+            "mainClass": "org.quiltmc.loader.impl.launch.knot.KnotClient",
+            "libraries": [],
+        }).to_string().into_bytes();
+        let server = crate::test_server::TestServer::start(vec![
+            ("/v3/versions/loader/1.20.1/0.23.1/profile/json", profile),
+        ]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = LoaderConfig { quilt_meta_url: server.url("/v3"), ..Default::default() };
+        let loader = Loader { kind: LoaderKind::Quilt, version: String::from("0.23.1") };
+
+        let version = install_loader(&config, &loader, "1.20.1", dir.path()).await.ok().unwrap();
+
+        assert_eq!(version.inherits_from.as_deref(), Some("1.20.1"));
+        assert!(dir.path().join("quilt-loader-0.23.1-1.20.1/quilt-loader-0.23.1-1.20.1.json").exists());
+    }
+}
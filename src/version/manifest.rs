@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use std::path::Path;
+use crate::download::{DownloadError, File};
+use crate::network;
+use super::{Version, VersionError};
+
+#[derive(Deserialize, Debug)]
+pub struct Latest {
+    pub release: String,
+    pub snapshot: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VersionEntry {
+    pub id: String,
+    pub url: String,
+    pub sha1: String,
+}
+
+//version_manifest_v2.json, the list of every published version
+#[derive(Deserialize, Debug)]
+pub struct VersionManifest {
+    pub latest: Latest,
+    pub versions: Vec<VersionEntry>,
+}
+
+impl VersionManifest {
+    //fetched and kept at cache_path when online, read back from it when offline
+    pub async fn load(url: &str, cache_path: &Path) -> Result<VersionManifest, VersionError> {
+        if network::is_offline() {
+            let bytes = std::fs::read(cache_path).map_err(|_| DownloadError::missing(cache_path))?;
+            return serde_json::from_slice(&bytes).map_err(VersionError::Json);
+        }
+        let resp = network::get_json(url).await.and_then(|r| r.error_for_status()).map_err(DownloadError::Reqwest)?;
+        let bytes = network::body(resp).await?;
+        let manifest = serde_json::from_slice(&bytes).map_err(VersionError::Json)?;
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent).map_err(VersionError::Io)?;
+        }
+        std::fs::write(cache_path, &bytes).map_err(VersionError::Io)?;
+        Ok(manifest)
+    }
+
+    //"latest-release" and "latest-snapshot" are aliases of the versions they currently point to
+    pub fn resolve<'a>(&'a self, id: &'a str) -> &'a str {
+        match id {
+            "latest-release" => self.latest.release.as_str(),
+            "latest-snapshot" => self.latest.snapshot.as_str(),
+            id => id,
+        }
+    }
+
+    pub fn find(&self, id: &str) -> Option<&VersionEntry> {
+        let id = self.resolve(id);
+        self.versions.iter().find(|v| v.id == id)
+    }
+}
+
+impl VersionEntry {
+    //download (or reuse) versions/<id>/<id>.json
+    pub async fn fetch(&self, versions_dir: &Path) -> Result<Version, VersionError> {
+        let file = File {
+            url: self.url.clone(),
+            size: 0,
+            hash: self.sha1.clone(),
+            path: format!("{0}/{0}.json", self.id),
+            ..Default::default()
+        };
+        file.ensure(&versions_dir.to_string_lossy()).await?;
+        Version::from_path(versions_dir.join(&file.path))
+    }
+}
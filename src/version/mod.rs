@@ -0,0 +1,119 @@
+mod rules;
+mod library;
+mod natives;
+mod assets;
+mod manifest;
+mod arguments;
+mod loader;
+mod forge;
+pub use rules::*;
+pub use library::*;
+pub use natives::*;
+pub use assets::*;
+pub use manifest::*;
+pub use arguments::*;
+pub use loader::*;
+pub use forge::*;
+
+use serde::Deserialize;
+use std::path::Path;
+use core::fmt;
+use crate::download::{DownloadError, File};
+
+pub enum VersionError {
+    Download(DownloadError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Zip(zip::result::ZipError),
+    UnsupportedLoader(String),
+    Processor(String),
+    InvalidHash(String),
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionError::Download(e) => write!(f, "Download error: {}", e),
+            VersionError::Io(e) => write!(f, "IO error: {}", e),
+            VersionError::Json(e) => write!(f, "JSON error: {}", e),
+            VersionError::Zip(e) => write!(f, "Zip error: {}", e),
+            VersionError::UnsupportedLoader(id) => write!(f, "Unsupported loader {}", id),
+            VersionError::Processor(e) => write!(f, "Loader installer error: {}", e),
+            VersionError::InvalidHash(hash) => write!(f, "Invalid hash {}", hash),
+        }
+    }
+}
+
+impl From<DownloadError> for VersionError {
+    fn from(e: DownloadError) -> Self {
+        VersionError::Download(e)
+    }
+}
+
+//the subset of a version json (versions/<id>/<id>.json) the launcher understands
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+    pub id: String,
+    //loader profiles only list what they add to this version
+    pub inherits_from: Option<String>,
+    //id of the version whose client jar is used, the version itself when unset
+    pub jar: Option<String>,
+    #[serde(default)]
+    pub libraries: Vec<Library>,
+    pub asset_index: Option<AssetIndexRef>,
+    pub assets: Option<String>,
+    pub main_class: String,
+    pub arguments: Option<Arguments>,
+    pub minecraft_arguments: Option<String>,
+    #[serde(default)]
+    pub downloads: VersionDownloads,
+    pub java_version: Option<JavaVersion>,
+    #[serde(rename = "type", default)]
+    pub version_type: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct VersionDownloads {
+    pub client: Option<Artifact>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JavaVersion {
+    pub component: String,
+    pub major_version: u32,
+}
+
+impl Default for JavaVersion {
+    //versions without "javaVersion" predate 1.17 and run on java 8
+    fn default() -> Self {
+        Self {
+            component: String::from("jre-legacy"),
+            major_version: 8,
+        }
+    }
+}
+
+impl Version {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Version, VersionError> {
+        let bytes = std::fs::read(path).map_err(VersionError::Io)?;
+        serde_json::from_slice(&bytes).map_err(VersionError::Json)
+    }
+
+    //versions/<jar>/<jar>.jar
+    pub fn client_file(&self) -> Option<File> {
+        let client = self.downloads.client.as_ref()?;
+        Some(File {
+            url: client.url.clone(),
+            size: client.size,
+            hash: client.sha1.clone(),
+            path: format!("{0}/{0}.jar", self.jar.as_deref().unwrap_or(&self.id)),
+            ..Default::default()
+        })
+    }
+
+    pub fn libraries(&self, platform: &Platform, features: &Features) -> LibraryPlan {
+        resolve_libraries(&self.libraries, platform, features)
+    }
+}
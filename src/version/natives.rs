@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::log;
+use super::{Native, VersionError};
+
+//every launch gets its own natives directory so a running game never has its dlls overwritten
+pub fn natives_directory(root: &Path, version_id: &str) -> PathBuf {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    root.join("natives").join(format!("{}-{}", version_id, stamp))
+}
+
+//remove natives directories of previous launches of the same version, the ones still in use are locked
+//and kept. Other versions may be running from the same root, their natives aren't locked everywhere
+pub fn clean_natives(root: &Path, version_id: &str, current: &Path) {
+    let Ok(entries) = std::fs::read_dir(root.join("natives")) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let previous = entry.file_name().to_str()
+            .and_then(|name| name.strip_prefix(version_id)?.strip_prefix('-'))
+            .is_some_and(|stamp| !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit()));
+        if previous && path != current && std::fs::remove_dir_all(&path).is_err() {
+            log!("INFO", path.display(), "natives still in use, kept");
+        }
+    }
+}
+
+pub fn extract_natives(natives: &[Native], libraries_dir: &Path, target: &Path) -> Result<(), VersionError> {
+    std::fs::create_dir_all(target).map_err(VersionError::Io)?;
+    for native in natives {
+        let jar = std::fs::File::open(libraries_dir.join(&native.path)).map_err(VersionError::Io)?;
+        let mut archive = zip::ZipArchive::new(jar).map_err(VersionError::Zip)?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).map_err(VersionError::Zip)?;
+            if entry.is_dir() || native.exclude.iter().any(|prefix| entry.name().starts_with(prefix.as_str())) {
+                continue;
+            }
+            let Some(name) = entry.enclosed_name().map(|p| p.to_path_buf()) else {
+                log!("WARN", entry.name(), "unsafe path in {}, skipped", native.path);
+                continue;
+            };
+            let path = target.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(VersionError::Io)?;
+            }
+            let mut file = std::fs::File::create(&path).map_err(VersionError::Io)?;
+            std::io::copy(&mut entry, &mut file).map_err(VersionError::Io)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_only_previous_launches_of_the_version() {
+        let root = tempfile::tempdir().unwrap();
+        let natives = root.path().join("natives");
+        for name in ["1.20.1-100", "1.20.1-200", "1.20.1-pre1-300", "1.12.2-400", "1.20.1-"] {
+            std::fs::create_dir_all(natives.join(name)).unwrap();
+        }
+
+        clean_natives(root.path(), "1.20.1", &natives.join("1.20.1-200"));
+
+        let mut left = std::fs::read_dir(&natives).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["1.12.2-400", "1.20.1-", "1.20.1-200", "1.20.1-pre1-300"]);
+    }
+
+    #[test]
+    fn extracts_natives_without_the_excluded_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let (libraries, target) = (dir.path().join("libraries"), dir.path().join("natives"));
+        let jar = "org/lwjgl/lwjgl/lwjgl-platform/2.9.4-nightly-20150209/lwjgl-platform-2.9.4-nightly-20150209-natives-linux.jar";
+        std::fs::create_dir_all(libraries.join(jar).parent().unwrap()).unwrap();
+        std::fs::write(libraries.join(jar), crate::test_server::zip_fixture(&[
+            ("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0"),
+            ("META-INF/LWJGL.SF", b"signature"),
+            ("liblwjgl64.so", b"lwjgl"),
+            ("linux/libopenal64.so", b"openal"),
+            ("../escaped.so", b"escaped"),
+        ])).unwrap();
+        let natives = [Native { path: jar.to_string(), exclude: vec![String::from("META-INF/")] }];
+
+        extract_natives(&natives, &libraries, &target).ok().unwrap();
+
+        assert_eq!(std::fs::read(target.join("liblwjgl64.so")).unwrap(), b"lwjgl");
+        assert_eq!(std::fs::read(target.join("linux/libopenal64.so")).unwrap(), b"openal");
+        assert!(!target.join("META-INF").exists());
+        assert!(!dir.path().join("escaped.so").exists());
+    }
+}
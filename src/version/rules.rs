@@ -0,0 +1,165 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Disallow,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct OsRule {
+    pub name: Option<String>,
+    pub arch: Option<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Rule {
+    pub action: Action,
+    pub os: Option<OsRule>,
+    #[serde(default)]
+    pub features: HashMap<String, bool>,
+}
+
+pub type Features = HashMap<String, bool>;
+
+//the platform as named by the version json ("windows", "osx", "linux")
+pub struct Platform {
+    pub name: &'static str,
+    pub arch: &'static str,
+    pub version: String,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        let name = match std::env::consts::OS {
+            "windows" => "windows",
+            "macos" => "osx",
+            _ => "linux",
+        };
+        let arch = match std::env::consts::ARCH {
+            "x86" => "x86",
+            "aarch64" => "arm64",
+            "arm" => "arm32",
+            _ => "x86_64",
+        };
+        Self {
+            name,
+            arch,
+            version: os_info::get().version().to_string(),
+        }
+    }
+
+    //value substituted to ${arch} in legacy natives classifiers
+    pub fn bits(&self) -> &'static str {
+        match self.arch {
+            "x86" | "arm32" => "32",
+            _ => "64",
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, platform: &Platform, features: &Features) -> bool {
+        if let Some(os) = &self.os {
+            if os.name.as_ref().is_some_and(|name| name != platform.name) {
+                return false;
+            }
+            if os.arch.as_ref().is_some_and(|arch| arch != platform.arch) {
+                return false;
+            }
+            if let Some(version) = &os.version {
+                match regex::Regex::new(version) {
+                    Ok(re) if re.is_match(&platform.version) => {}
+                    _ => return false,
+                }
+            }
+        }
+        self.features
+            .iter()
+            .all(|(feature, value)| features.get(feature).copied().unwrap_or(false) == *value)
+    }
+}
+
+//no rules means allowed, otherwise the last matching rule wins
+pub fn rules_allow(rules: &[Rule], platform: &Platform, features: &Features) -> bool {
+    if rules.is_empty() {
+        return true;
+    }
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(platform, features))
+        .is_some_and(|rule| rule.action == Action::Allow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn platform(name: &'static str, arch: &'static str, version: &str) -> Platform {
+        Platform { name, arch, version: version.to_string() }
+    }
+
+    fn rules(json: serde_json::Value) -> Vec<Rule> {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn matches_os_and_features() {
+        let linux = platform("linux", "x86_64", "6.5.0");
+        let windows_32 = platform("windows", "x86", "10.0.19045");
+        let leopard = platform("osx", "x86_64", "10.5.8");
+        let demo = Features::from([(String::from("is_demo_user"), true)]);
+        let cases = [
+            (serde_json::json!({ "action": "allow", "os": { "name": "linux" } }), &linux, Features::new(), true),
+            (serde_json::json!({ "action": "allow", "os": { "name": "osx" } }), &linux, Features::new(), false),
+            (serde_json::json!({ "action": "allow", "os": { "arch": "x86" } }), &windows_32, Features::new(), true),
+            (serde_json::json!({ "action": "allow", "os": { "arch": "x86" } }), &linux, Features::new(), false),
+            (serde_json::json!({ "action": "allow", "os": { "name": "osx", "version": "^10\\.5\\.\\d$" } }), &leopard, Features::new(), true),
+            (serde_json::json!({ "action": "allow", "os": { "name": "osx", "version": "^10\\.5\\.\\d$" } }), &platform("osx", "arm64", "14.1"), Features::new(), false),
+            (serde_json::json!({ "action": "allow", "os": { "version": "(" } }), &linux, Features::new(), false),
+            (serde_json::json!({ "action": "allow", "features": { "is_demo_user": true } }), &linux, demo.clone(), true),
+            (serde_json::json!({ "action": "allow", "features": { "is_demo_user": true } }), &linux, Features::new(), false),
+            (serde_json::json!({ "action": "allow", "features": { "is_demo_user": false } }), &linux, Features::new(), true),
+            (serde_json::json!({ "action": "allow", "features": { "has_custom_resolution": true } }), &linux, demo, false),
+        ];
+        for (rule, platform, features, expected) in cases {
+            let rule: Rule = serde_json::from_value(rule.clone()).unwrap();
+            assert_eq!(rule.matches(platform, &features), expected, "{:?} on {}", rule, platform.name);
+        }
+    }
+
+    #[test]
+    fn the_last_matching_rule_wins() {
+        let linux = platform("linux", "x86_64", "6.5.0");
+        let leopard = platform("osx", "x86_64", "10.5.8");
+        let sonoma = platform("osx", "arm64", "14.1");
+        //lwjgl 2.9.0 of the old version jsons, and the twitch natives missing on linux
+        let not_on_leopard = rules(serde_json::json!([
+            { "action": "allow" },
+            { "action": "disallow", "os": { "name": "osx", "version": "^10\\.5\\.\\d$" } },
+        ]));
+        let only_on_leopard = rules(serde_json::json!([
+            { "action": "allow", "os": { "name": "osx", "version": "^10\\.5\\.\\d$" } },
+        ]));
+        let not_on_linux = rules(serde_json::json!([
+            { "action": "allow" },
+            { "action": "disallow", "os": { "name": "linux" } },
+        ]));
+        let cases = [
+            (&[][..], &linux, true),
+            (&not_on_leopard[..], &leopard, false),
+            (&not_on_leopard[..], &sonoma, true),
+            (&only_on_leopard[..], &leopard, true),
+            (&only_on_leopard[..], &linux, false),
+            (&not_on_linux[..], &linux, false),
+            (&not_on_linux[..], &sonoma, true),
+        ];
+        for (rules, platform, expected) in cases {
+            assert_eq!(rules_allow(rules, platform, &Features::new()), expected, "{:?} on {} {}", rules, platform.name, platform.version);
+        }
+    }
+}
@@ -20,11 +20,23 @@ mod impl_window
         
         bottom_layout: nwg::FlexboxLayout, 
         pub status_text: nwg::Label,
+        limit_button: nwg::Button,
         cancel_button: nwg::Button,
 
         icon: Option<nwg::Icon>
     }
 
+    //bandwidth limits the button cycles through, in bytes/s, 0 for unlimited
+    const LIMITS: [u64; 6] = [0, 8 << 20, 4 << 20, 2 << 20, 1 << 20, 512 << 10];
+
+    fn limit_text(limit: u64) -> String {
+        match limit {
+            0 => String::from("Débit illimité"),
+            limit if limit >= 1 << 20 => format!("{} Mo/s", limit >> 20),
+            limit => format!("{} Ko/s", limit >> 10),
+        }
+    }
+
     pub struct GuiApplicationUi {
         inner: Rc<GuiApplication>,
         default_handler: RefCell<Option<nwg::EventHandler>>
@@ -33,7 +45,7 @@ mod impl_window
         fn build_ui(mut data: GuiApplication) -> Result<GuiApplicationUi, nwg::NwgError> {
             use nwg::Event;
 
-            let (win_width, win_height) = (380, 125);
+            let (win_width, win_height) = (480, 125);
             
             let (screen_width, screen_height) = (nwg::Monitor::width(), nwg::Monitor::height());
             let (win_pos_x, win_pos_y) = ((screen_width-win_width)/2, (screen_height-win_height)/2);
@@ -58,6 +70,11 @@ mod impl_window
                 .flags(nwg::LabelFlags::ELIPSIS | nwg::LabelFlags::VISIBLE)
                 .build(&mut data.status_text)?;
 
+            nwg::Button::builder()
+                .parent(&data.window)
+                .text(&limit_text(crate::network::bandwidth_limit()))
+                .build(&mut data.limit_button)?;
+
             nwg::Button::builder()
                 .parent(&data.window)
                 .text("Arrêter")
@@ -74,6 +91,7 @@ mod impl_window
                     match evt {
                         Event::OnWindowClose if &handle == &evt_ui.window => GuiApplication::on_stop(&evt_ui),
                         Event::OnButtonClick if &handle == &evt_ui.cancel_button => GuiApplication::on_stop(&evt_ui),
+                        Event::OnButtonClick if &handle == &evt_ui.limit_button => GuiApplication::on_limit(&evt_ui),
                         Event::OnInit if &handle == &evt_ui.window => GuiApplication::on_init(&evt_ui),
                         _ => {}
                     }
@@ -88,6 +106,8 @@ mod impl_window
                 .child(&ui.status_text)
                     .child_flex_grow(1.)
                     .child_size(Size{width: D::Auto, height: D::Auto})
+                .child(&ui.limit_button)
+                    .child_size(Size{width: D::Points(110.), height: D::Auto})
                 .child(&ui.cancel_button)
                     .child_size(Size{width: D::Points(100.), height: D::Auto})
                     .child_margin(Rect { start: D::Points(0.), end: D::Points(0.), top: D::Points(0.), bottom: D::Points(0.) })
@@ -139,6 +159,13 @@ mod impl_window
         fn on_init(&self) {
             self.window.set_visible(true);
         }
+        //next limit of LIMITS, applied to the downloads in progress
+        fn on_limit(&self) {
+            let current = crate::network::bandwidth_limit();
+            let next = LIMITS.iter().position(|l| *l == current).map_or(LIMITS[0], |i| LIMITS[(i + 1) % LIMITS.len()]);
+            crate::network::set_bandwidth_limit(next);
+            self.limit_button.set_text(&limit_text(next));
+        }
         fn on_stop(&self) {
             nwg::stop_thread_dispatch();
         }
@@ -153,6 +180,7 @@ pub enum Signal {
     Quit,
     AdvanceDelta(u32),
     Advance,
+    Reset,
     UpdateStatus(String),
 }
 #[must_use]
@@ -170,6 +198,7 @@ pub fn make_window(events: mpsc::Receiver<Signal>, range: Range<u32>) -> std::th
                     Signal::Quit => app.close(),
                     Signal::AdvanceDelta(delta) => app.progress_bar.advance_delta(delta),
                     Signal::Advance => app.progress_bar.advance(),
+                    Signal::Reset => app.progress_bar.set_pos(app.progress_bar.range().start),
                     Signal::UpdateStatus(status) => status_text = Some(status),
                 }
             }